@@ -0,0 +1,162 @@
+use std::{collections::HashMap, path::PathBuf};
+
+use anyhow::{anyhow, Result};
+use unicode_segmentation::UnicodeSegmentation;
+
+use crate::{
+    cursor::Cursor,
+    history::{Edit, History},
+    mark,
+    search::Search,
+    selection::Selection,
+    syntax::{Highlighter, LanguageRegistry},
+    text_buffer::TextBuffer,
+    util::is_executable,
+};
+
+// Everything that belongs to one open file. The editor keeps a list of these and edits the
+// current one.
+#[derive(Default)]
+pub struct Buffer {
+    // Stable number shown by :ls and accepted by :buffer, never reused
+    pub id: usize,
+    pub text: TextBuffer,
+    pub cursor: Cursor,
+    // TODO: Make this absolute path
+    pub filename: Option<PathBuf>,
+    pub scroll: (usize, usize),
+    pub search: Search,
+    pub history: History,
+    pub saved_revision: usize,
+    pub highlighter: Option<Highlighter>,
+    pub last_visual: Option<Selection>,
+    // Marks set in this buffer, including the global ones A-Z that were set here
+    pub marks: HashMap<char, Cursor>,
+}
+
+impl Buffer {
+    pub fn new(id: usize) -> Self {
+        Self {
+            id,
+            ..Default::default()
+        }
+    }
+
+    // A path that does not exist yet gives an empty buffer that is created on write
+    pub fn open(id: usize, path: PathBuf, languages: &LanguageRegistry) -> Result<Self> {
+        if path.exists() && !path.is_file() {
+            return Err(anyhow!("Path is not file"));
+        }
+        if path.exists() && is_executable(&path)? {
+            return Err(anyhow!("Cannot open executable"));
+        }
+
+        let mut buffer = Self::new(id);
+        if path.exists() {
+            buffer.text = TextBuffer::from_file(&path)?;
+        }
+        buffer.filename = Some(path);
+        buffer.detect_language(languages);
+        Ok(buffer)
+    }
+
+    pub fn detect_language(&mut self, languages: &LanguageRegistry) {
+        self.highlighter = self
+            .filename
+            .as_ref()
+            .and_then(|path| languages.for_path(path))
+            .map(Highlighter::new);
+    }
+
+    pub fn name(&self) -> String {
+        match &self.filename {
+            Some(path) => path.display().to_string(),
+            None => "[No Name]".to_string(),
+        }
+    }
+
+    pub fn is_dirty(&self) -> bool {
+        self.history.current() != self.saved_revision
+    }
+
+    // Unnamed and never edited, opening a file can take its place
+    pub fn is_pristine(&self) -> bool {
+        self.filename.is_none() && self.history.len() == 1 && self.text.len_chars() == 0
+    }
+
+    // Global marks set here, with the file they stay with when the buffer is deleted
+    pub fn file_marks(&self) -> Vec<(char, (PathBuf, Cursor))> {
+        let Some(path) = &self.filename else {
+            return vec![];
+        };
+        self.marks
+            .iter()
+            .filter(|(name, _)| mark::is_global(**name))
+            .map(|(name, cursor)| (*name, (path.clone(), *cursor)))
+            .collect()
+    }
+
+    // Returns the position just past the inserted text
+    pub fn insert_text(&mut self, pos: Cursor, text: &str) -> Cursor {
+        let at = self.text.pos_to_char(pos);
+        let edit = Edit::Insert {
+            at,
+            text: text.to_string(),
+        };
+        self.apply_edit(&edit);
+        self.history.record(edit, self.cursor);
+        self.text.char_to_pos(at + text.chars().count())
+    }
+
+    pub fn delete_text(&mut self, start: Cursor, end: Cursor) -> String {
+        let text = self.text_between(start, end);
+        if text.is_empty() {
+            return text;
+        }
+
+        let edit = Edit::Delete {
+            at: self.text.pos_to_char(start),
+            text: text.clone(),
+        };
+        self.apply_edit(&edit);
+        self.history.record(edit, self.cursor);
+        text
+    }
+
+    pub fn text_between(&self, start: Cursor, end: Cursor) -> String {
+        self.text.slice(start, end)
+    }
+
+    pub fn apply_edit(&mut self, edit: &Edit) {
+        if let Some(highlighter) = &mut self.highlighter {
+            let at = match edit {
+                Edit::Insert { at, .. } | Edit::Delete { at, .. } => *at,
+            };
+            highlighter.invalidate(self.text.char_to_pos(at).y);
+        }
+        let marks = mark::adjust(&self.marks, &self.text, edit);
+        match edit {
+            Edit::Insert { at, text } => self.text.insert(*at, text),
+            Edit::Delete { at, text } => self.text.remove(*at..*at + text.chars().count()),
+        }
+        self.marks = marks
+            .into_iter()
+            .map(|(name, idx)| (name, self.text.char_to_pos(idx)))
+            .collect();
+    }
+
+    // Column of the first character on line `y` that isn't whitespace
+    pub fn first_non_blank(&self, y: usize) -> usize {
+        let line = self.text.line(y).unwrap_or_default();
+        let graphemes = line.graphemes(true).collect::<Vec<_>>();
+        graphemes
+            .iter()
+            .position(|g| !g.chars().all(char::is_whitespace))
+            .unwrap_or(graphemes.len())
+    }
+
+    pub fn char_at(&self, coords: (usize, usize)) -> Option<char> {
+        let line = self.text.line(coords.1)?;
+        line.graphemes(true).nth(coords.0)?.chars().next()
+    }
+}
@@ -5,8 +5,8 @@ use clap::Parser;
 #[derive(Parser, Debug)]
 #[command(version)]
 pub struct Cli {
-    /// File to edit
-    pub file: Option<String>,
+    /// Files to edit, each opened in its own buffer
+    pub files: Vec<String>,
     /// Use this config 
     #[arg(short = 'u')]
     pub config: Option<PathBuf>,
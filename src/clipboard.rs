@@ -0,0 +1,127 @@
+use std::{
+    io::Write,
+    process::{Command, Stdio},
+};
+
+use anyhow::{anyhow, Context, Result};
+
+use crate::{
+    config::ClipboardProvider,
+    register::{Register, RegisterKind},
+};
+
+// The system clipboard behind the + and * registers
+#[derive(Default)]
+pub struct Clipboard {
+    provider: ClipboardProvider,
+    // The last copy. Pasting gives it back as long as the clipboard still holds the same text, so
+    // yanked lines are put as lines.
+    last: Register,
+    // OSC 52 sequences waiting to be written to the terminal by the main loop
+    pending: Vec<String>,
+}
+
+impl Clipboard {
+    pub fn new(provider: ClipboardProvider) -> Self {
+        Self {
+            provider,
+            ..Default::default()
+        }
+    }
+
+    pub fn set(&mut self, register: Register) -> Result<()> {
+        match &self.provider {
+            ClipboardProvider::Internal => {}
+            ClipboardProvider::Osc52 => self.pending.push(osc52(&register.text)),
+            ClipboardProvider::Command { copy, .. } => run_copy(copy, &register.text)?,
+        }
+        self.last = register;
+        Ok(())
+    }
+
+    pub fn get(&self) -> Result<Register> {
+        let paste = match &self.provider {
+            ClipboardProvider::Command { paste, .. } if !paste.is_empty() => paste,
+            _ => return Ok(self.last.clone()),
+        };
+        let text = run_paste(paste)?;
+        if text == self.last.text {
+            return Ok(self.last.clone());
+        }
+        // Text copied elsewhere is put as lines when it ends in a newline, as in vim
+        let kind = match text.ends_with('\n') {
+            true => RegisterKind::Linewise,
+            false => RegisterKind::Charwise,
+        };
+        Ok(Register::new(text, kind))
+    }
+
+    pub fn take_pending(&mut self) -> Vec<String> {
+        std::mem::take(&mut self.pending)
+    }
+}
+
+fn spawn(command: &[String], stdin: Stdio, stdout: Stdio) -> Result<std::process::Child> {
+    let (program, args) = command
+        .split_first()
+        .ok_or_else(|| anyhow!("Clipboard command is empty"))?;
+    // stderr would end up on top of the editor
+    Command::new(program)
+        .args(args)
+        .stdin(stdin)
+        .stdout(stdout)
+        .stderr(Stdio::null())
+        .spawn()
+        .with_context(|| format!("Failed to run clipboard command {}", program))
+}
+
+fn run_copy(command: &[String], text: &str) -> Result<()> {
+    let mut child = spawn(command, Stdio::piped(), Stdio::null())?;
+    let written = match child.stdin.take() {
+        Some(mut stdin) => stdin.write_all(text.as_bytes()),
+        None => Ok(()),
+    };
+    // A command that fails early closes its input, the failure says more than the broken pipe
+    if !child.wait()?.success() {
+        return Err(anyhow!("Clipboard command {} failed", command[0]));
+    }
+    Ok(written?)
+}
+
+fn run_paste(command: &[String]) -> Result<String> {
+    let child = spawn(command, Stdio::null(), Stdio::piped())?;
+    let output = child.wait_with_output()?;
+    if !output.status.success() {
+        return Err(anyhow!("Clipboard command {} failed", command[0]));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+// Sets the clipboard of the terminal, `c`, to the base64 encoded text. tmux drops the sequence
+// unless it comes wrapped for passthrough, with its escapes doubled.
+fn osc52(text: &str) -> String {
+    let sequence = format!("\x1b]52;c;{}\x07", base64(text.as_bytes()));
+    match std::env::var_os("TMUX") {
+        Some(_) => format!("\x1bPtmux;{}\x1b\\", sequence.replace('\x1b', "\x1b\x1b")),
+        None => sequence,
+    }
+}
+
+fn base64(bytes: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut encoded = String::with_capacity(bytes.len().div_ceil(3) * 4);
+    for chunk in bytes.chunks(3) {
+        let n = chunk
+            .iter()
+            .enumerate()
+            .fold(0u32, |n, (i, byte)| n | (*byte as u32) << (16 - 8 * i));
+        for i in 0..4 {
+            if i <= chunk.len() {
+                encoded.push(ALPHABET[(n >> (18 - 6 * i) & 0x3f) as usize] as char);
+            } else {
+                encoded.push('=');
+            }
+        }
+    }
+    encoded
+}
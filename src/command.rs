@@ -0,0 +1,657 @@
+use anyhow::{anyhow, Result};
+
+use crate::{editor::Editor, window::SplitDirection};
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum CommandKind {
+    Write,
+    Quit,
+    WriteQuit,
+    Exit,
+    Edit,
+    SaveAs,
+    Undo,
+    Redo,
+    Earlier,
+    Later,
+    NoHighlight,
+    Substitute,
+    Goto,
+    Buffer,
+    BufferNext,
+    BufferPrev,
+    BufferDelete,
+    BufferList,
+    Split,
+    New,
+    Close,
+    Only,
+    Resize,
+    WindowCommand,
+    QuitAll,
+    Vertical,
+    TabNew,
+    TabClose,
+    TabOnly,
+    TabNext,
+    TabPrev,
+    TabFirst,
+    TabLast,
+    TabMove,
+    Registers,
+    Yank,
+    Marks,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Arg {
+    None,
+    Optional,
+    Required,
+    // Passed through untrimmed, for commands like :s/a/b/ that need no space before their
+    // argument
+    Raw,
+}
+
+struct CommandSpec {
+    name: &'static str,
+    // Shortest accepted abbreviation, e.g. 1 lets "write" be typed as "w"
+    min_len: usize,
+    kind: CommandKind,
+    bang: bool,
+    range: bool,
+    arg: Arg,
+}
+
+// Order matters: the first spec that accepts an abbreviation wins
+#[rustfmt::skip]
+const COMMANDS: &[CommandSpec] = &[
+    CommandSpec { name: "write", min_len: 1, kind: CommandKind::Write, bang: true, range: false, arg: Arg::Optional },
+    CommandSpec { name: "wq", min_len: 2, kind: CommandKind::WriteQuit, bang: true, range: false, arg: Arg::Optional },
+    CommandSpec { name: "quit", min_len: 1, kind: CommandKind::Quit, bang: true, range: false, arg: Arg::None },
+    CommandSpec { name: "xit", min_len: 1, kind: CommandKind::Exit, bang: true, range: false, arg: Arg::Optional },
+    CommandSpec { name: "exit", min_len: 3, kind: CommandKind::Exit, bang: true, range: false, arg: Arg::Optional },
+    CommandSpec { name: "edit", min_len: 1, kind: CommandKind::Edit, bang: true, range: false, arg: Arg::Optional },
+    CommandSpec { name: "saveas", min_len: 3, kind: CommandKind::SaveAs, bang: true, range: false, arg: Arg::Required },
+    CommandSpec { name: "undo", min_len: 1, kind: CommandKind::Undo, bang: false, range: false, arg: Arg::Optional },
+    CommandSpec { name: "redo", min_len: 3, kind: CommandKind::Redo, bang: false, range: false, arg: Arg::None },
+    CommandSpec { name: "earlier", min_len: 2, kind: CommandKind::Earlier, bang: false, range: false, arg: Arg::Optional },
+    CommandSpec { name: "later", min_len: 3, kind: CommandKind::Later, bang: false, range: false, arg: Arg::Optional },
+    CommandSpec { name: "substitute", min_len: 1, kind: CommandKind::Substitute, bang: false, range: true, arg: Arg::Raw },
+    CommandSpec { name: "buffer", min_len: 1, kind: CommandKind::Buffer, bang: false, range: false, arg: Arg::Optional },
+    CommandSpec { name: "bnext", min_len: 2, kind: CommandKind::BufferNext, bang: false, range: false, arg: Arg::Optional },
+    CommandSpec { name: "bprevious", min_len: 2, kind: CommandKind::BufferPrev, bang: false, range: false, arg: Arg::Optional },
+    CommandSpec { name: "bNext", min_len: 2, kind: CommandKind::BufferPrev, bang: false, range: false, arg: Arg::Optional },
+    CommandSpec { name: "bdelete", min_len: 2, kind: CommandKind::BufferDelete, bang: true, range: false, arg: Arg::Optional },
+    CommandSpec { name: "buffers", min_len: 7, kind: CommandKind::BufferList, bang: false, range: false, arg: Arg::None },
+    CommandSpec { name: "ls", min_len: 2, kind: CommandKind::BufferList, bang: false, range: false, arg: Arg::None },
+    CommandSpec { name: "files", min_len: 5, kind: CommandKind::BufferList, bang: false, range: false, arg: Arg::None },
+    CommandSpec { name: "split", min_len: 2, kind: CommandKind::Split, bang: false, range: false, arg: Arg::Optional },
+    CommandSpec { name: "vsplit", min_len: 2, kind: CommandKind::Split, bang: false, range: false, arg: Arg::Optional },
+    CommandSpec { name: "new", min_len: 3, kind: CommandKind::New, bang: false, range: false, arg: Arg::None },
+    CommandSpec { name: "vnew", min_len: 3, kind: CommandKind::New, bang: false, range: false, arg: Arg::None },
+    CommandSpec { name: "close", min_len: 3, kind: CommandKind::Close, bang: true, range: false, arg: Arg::None },
+    CommandSpec { name: "only", min_len: 2, kind: CommandKind::Only, bang: true, range: false, arg: Arg::None },
+    CommandSpec { name: "resize", min_len: 3, kind: CommandKind::Resize, bang: false, range: false, arg: Arg::Optional },
+    CommandSpec { name: "wincmd", min_len: 4, kind: CommandKind::WindowCommand, bang: false, range: false, arg: Arg::Required },
+    CommandSpec { name: "qall", min_len: 2, kind: CommandKind::QuitAll, bang: true, range: false, arg: Arg::None },
+    CommandSpec { name: "quitall", min_len: 5, kind: CommandKind::QuitAll, bang: true, range: false, arg: Arg::None },
+    CommandSpec { name: "vertical", min_len: 4, kind: CommandKind::Vertical, bang: false, range: false, arg: Arg::Raw },
+    CommandSpec { name: "tabnew", min_len: 6, kind: CommandKind::TabNew, bang: false, range: false, arg: Arg::Optional },
+    CommandSpec { name: "tabedit", min_len: 4, kind: CommandKind::TabNew, bang: false, range: false, arg: Arg::Optional },
+    CommandSpec { name: "tabclose", min_len: 4, kind: CommandKind::TabClose, bang: true, range: false, arg: Arg::Optional },
+    CommandSpec { name: "tabonly", min_len: 4, kind: CommandKind::TabOnly, bang: true, range: false, arg: Arg::None },
+    CommandSpec { name: "tabnext", min_len: 4, kind: CommandKind::TabNext, bang: false, range: false, arg: Arg::Optional },
+    CommandSpec { name: "tabprevious", min_len: 4, kind: CommandKind::TabPrev, bang: false, range: false, arg: Arg::Optional },
+    CommandSpec { name: "tabNext", min_len: 4, kind: CommandKind::TabPrev, bang: false, range: false, arg: Arg::Optional },
+    CommandSpec { name: "tabfirst", min_len: 6, kind: CommandKind::TabFirst, bang: false, range: false, arg: Arg::None },
+    CommandSpec { name: "tabrewind", min_len: 4, kind: CommandKind::TabFirst, bang: false, range: false, arg: Arg::None },
+    CommandSpec { name: "tablast", min_len: 4, kind: CommandKind::TabLast, bang: false, range: false, arg: Arg::None },
+    CommandSpec { name: "tabmove", min_len: 4, kind: CommandKind::TabMove, bang: false, range: false, arg: Arg::Optional },
+    CommandSpec { name: "registers", min_len: 3, kind: CommandKind::Registers, bang: false, range: false, arg: Arg::Optional },
+    CommandSpec { name: "display", min_len: 2, kind: CommandKind::Registers, bang: false, range: false, arg: Arg::Optional },
+    CommandSpec { name: "marks", min_len: 5, kind: CommandKind::Marks, bang: false, range: false, arg: Arg::Optional },
+    CommandSpec { name: "yank", min_len: 1, kind: CommandKind::Yank, bang: false, range: true, arg: Arg::Optional },
+    CommandSpec { name: "nohlsearch", min_len: 3, kind: CommandKind::NoHighlight, bang: false, range: false, arg: Arg::None },
+];
+
+#[derive(Clone, Copy, Debug)]
+enum AddressBase {
+    Line(usize),
+    Current,
+    Last,
+    Mark(char),
+}
+
+#[derive(Clone, Copy, Debug)]
+struct Address {
+    base: AddressBase,
+    offset: isize,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct LineRange {
+    start: Address,
+    end: Address,
+}
+
+#[derive(Debug)]
+pub struct Command {
+    pub kind: CommandKind,
+    // Set by the :vertical modifier, and by :vsplit and :vnew
+    pub vertical: bool,
+    pub bang: bool,
+    pub range: Option<LineRange>,
+    pub arg: Option<String>,
+}
+
+// None without digits, an error when they don't fit
+fn parse_number(input: &str) -> Result<(Option<usize>, &str)> {
+    let len = input
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(input.len());
+    let number = match len {
+        0 => None,
+        _ => Some(input[..len].parse().map_err(|_| invalid_range())?),
+    };
+    Ok((number, &input[len..]))
+}
+
+fn invalid_range() -> anyhow::Error {
+    anyhow!("E16: Invalid range")
+}
+
+fn parse_address(input: &str) -> Result<(Option<Address>, &str)> {
+    let mut chars = input.chars();
+    let (base, mut rest) = match chars.next() {
+        Some('.') => (Some(AddressBase::Current), chars.as_str()),
+        Some('$') => (Some(AddressBase::Last), chars.as_str()),
+        Some('\'') => match chars.next() {
+            Some(mark) => (Some(AddressBase::Mark(mark)), chars.as_str()),
+            None => return Err(anyhow!("E20: Mark not set")),
+        },
+        Some(c) if c.is_ascii_digit() => {
+            let (line, rest) = parse_number(input)?;
+            (line.map(AddressBase::Line), rest)
+        }
+        _ => (None, input),
+    };
+
+    let mut offset: isize = 0;
+    let mut has_offset = false;
+    while let Some(sign) = rest.chars().next().filter(|c| *c == '+' || *c == '-') {
+        let (amount, next) = parse_number(&rest[1..])?;
+        let amount = isize::try_from(amount.unwrap_or(1)).map_err(|_| invalid_range())?;
+        offset = match sign {
+            '+' => offset.checked_add(amount),
+            _ => offset.checked_sub(amount),
+        }
+        .ok_or_else(invalid_range)?;
+        has_offset = true;
+        rest = next;
+    }
+
+    let address = match (base, has_offset) {
+        (Some(base), _) => Some(Address { base, offset }),
+        (None, true) => Some(Address {
+            base: AddressBase::Current,
+            offset,
+        }),
+        (None, false) => None,
+    };
+    Ok((address, rest))
+}
+
+fn parse_range(input: &str) -> Result<(Option<LineRange>, &str)> {
+    if let Some(rest) = input.strip_prefix('%') {
+        let range = LineRange {
+            start: Address {
+                base: AddressBase::Line(1),
+                offset: 0,
+            },
+            end: Address {
+                base: AddressBase::Last,
+                offset: 0,
+            },
+        };
+        return Ok((Some(range), rest));
+    }
+
+    let (start, rest) = parse_address(input)?;
+    let Some(rest) = rest.strip_prefix(',') else {
+        return Ok((start.map(|start| LineRange { start, end: start }), rest));
+    };
+
+    let current = Address {
+        base: AddressBase::Current,
+        offset: 0,
+    };
+    let (end, rest) = parse_address(rest)?;
+    let range = LineRange {
+        start: start.unwrap_or(current),
+        end: end.unwrap_or(current),
+    };
+    Ok((Some(range), rest))
+}
+
+pub fn parse(input: &str) -> Result<Command> {
+    let input = input.trim_start_matches(|c: char| c == ':' || c.is_whitespace());
+    let (range, input) = parse_range(input)?;
+    let input = input.trim_start();
+    let name_len = input
+        .find(|c: char| !c.is_ascii_alphabetic())
+        .unwrap_or(input.len());
+    let (name, rest) = input.split_at(name_len);
+    if name.is_empty() {
+        if range.is_some() && rest.is_empty() {
+            return Ok(Command {
+                kind: CommandKind::Goto,
+                vertical: false,
+                bang: false,
+                range,
+                arg: None,
+            });
+        }
+        return Err(anyhow!("E492: Not an editor command: {}", input));
+    }
+
+    let spec = COMMANDS
+        .iter()
+        .find(|spec| name.len() >= spec.min_len && spec.name.starts_with(name))
+        .ok_or_else(|| anyhow!("E492: Not an editor command: {}", input))?;
+
+    if range.is_some() && !spec.range {
+        return Err(anyhow!("E481: No range allowed"));
+    }
+
+    // :vertical is a modifier for the command that follows it
+    if spec.kind == CommandKind::Vertical {
+        let mut command = parse(rest)?;
+        command.vertical = true;
+        return Ok(command);
+    }
+
+    let vertical = matches!(spec.name, "vsplit" | "vnew");
+    if spec.arg == Arg::Raw {
+        let arg = Some(rest.trim_start()).filter(|arg| !arg.is_empty());
+        return Ok(Command {
+            kind: spec.kind,
+            vertical,
+            bang: false,
+            range,
+            arg: arg.map(str::to_string),
+        });
+    }
+
+    let (bang, rest) = match rest.strip_prefix('!') {
+        Some(rest) => (true, rest),
+        None => (false, rest),
+    };
+    if bang && !spec.bang {
+        return Err(anyhow!("E477: No ! allowed"));
+    }
+
+    if !rest.is_empty() && !rest.starts_with(char::is_whitespace) {
+        return Err(anyhow!("E488: Trailing characters: {}", rest));
+    }
+    let arg = Some(rest.trim())
+        .filter(|arg| !arg.is_empty())
+        .map(str::to_string);
+
+    match (spec.arg, &arg) {
+        (Arg::None, Some(arg)) => return Err(anyhow!("E488: Trailing characters: {}", arg)),
+        (Arg::Required, None) => return Err(anyhow!("E471: Argument required")),
+        _ => {}
+    }
+
+    Ok(Command {
+        kind: spec.kind,
+        vertical,
+        bang,
+        range,
+        arg,
+    })
+}
+
+impl Address {
+    fn resolve(&self, editor: &Editor) -> Result<usize> {
+        let line = match self.base {
+            AddressBase::Line(line) => isize::try_from(line).map_err(|_| invalid_range())? - 1,
+            AddressBase::Current => editor.buffer.cursor.y as isize,
+            AddressBase::Last => editor.buffer.text.len_lines() as isize - 1,
+            AddressBase::Mark(mark) => editor
+                .mark_line(mark)
+                .ok_or_else(|| anyhow!("E20: Mark not set"))?
+                as isize,
+        }
+        .checked_add(self.offset)
+        .ok_or_else(invalid_range)?;
+
+        // Line 0 is the first line, as in :0 or :0,$
+        let line = if line == -1 { 0 } else { line };
+        if line < 0 || line >= editor.buffer.text.len_lines() as isize {
+            return Err(invalid_range());
+        }
+        Ok(line as usize)
+    }
+}
+
+impl LineRange {
+    // Zero based and inclusive, backwards ranges are swapped
+    pub fn resolve(&self, editor: &Editor) -> Result<(usize, usize)> {
+        let start = self.start.resolve(editor)?;
+        let end = self.end.resolve(editor)?;
+        Ok((start.min(end), start.max(end)))
+    }
+}
+
+impl Command {
+    fn count_arg(&self) -> Result<Option<usize>> {
+        self.arg
+            .as_deref()
+            .map(|arg| {
+                arg.parse()
+                    .map_err(|_| anyhow!("E474: Invalid argument: {}", arg))
+            })
+            .transpose()
+    }
+
+    // Tab pages are numbered from 1 in commands
+    fn tab_arg(&self) -> Result<Option<usize>> {
+        match self.count_arg()? {
+            Some(0) => Err(anyhow!("E475: Invalid argument: 0")),
+            count => Ok(count.map(|count| count - 1)),
+        }
+    }
+
+    // `[x] [count]` as taken by :yank, e.g. `:yank + 3`
+    fn register_arg(&self) -> Result<(Option<char>, Option<usize>)> {
+        let arg = self.arg.as_deref().unwrap_or_default();
+        let (register, rest) = match arg.chars().next() {
+            Some(name) if !name.is_ascii_digit() => (Some(name), arg[name.len_utf8()..].trim()),
+            _ => (None, arg),
+        };
+        let count = Some(rest)
+            .filter(|rest| !rest.is_empty())
+            .map(|rest| {
+                rest.parse()
+                    .map_err(|_| anyhow!("E488: Trailing characters: {}", rest))
+            })
+            .transpose()?;
+        Ok((register, count))
+    }
+
+    fn split_direction(&self) -> SplitDirection {
+        match self.vertical {
+            true => SplitDirection::Vertical,
+            false => SplitDirection::Horizontal,
+        }
+    }
+
+    pub fn execute(self, editor: &mut Editor) -> Result<()> {
+        match self.kind {
+            CommandKind::Write => editor.write(self.arg.as_deref(), self.bang, false),
+            CommandKind::Quit => editor.quit(self.bang),
+            CommandKind::WriteQuit => {
+                editor.write(self.arg.as_deref(), self.bang, false)?;
+                editor.quit(self.bang)
+            }
+            CommandKind::Exit => {
+                if self.arg.is_some() || editor.is_dirty() {
+                    editor.write(self.arg.as_deref(), self.bang, false)?;
+                }
+                editor.quit(self.bang)
+            }
+            CommandKind::Edit => editor.edit(self.arg.as_deref(), self.bang),
+            CommandKind::SaveAs => {
+                let path = self.arg.as_deref().unwrap_or_default();
+                editor.write(Some(path), self.bang, true)
+            }
+            CommandKind::Undo => {
+                match self.count_arg()? {
+                    Some(rev) => editor.undo_goto(rev),
+                    None => editor.undo(),
+                }
+                Ok(())
+            }
+            CommandKind::Redo => {
+                editor.redo();
+                Ok(())
+            }
+            CommandKind::Earlier => {
+                let count = self.count_arg()?.unwrap_or(1);
+                editor.undo_chronological(-(count as isize));
+                Ok(())
+            }
+            CommandKind::Later => {
+                let count = self.count_arg()?.unwrap_or(1);
+                editor.undo_chronological(count as isize);
+                Ok(())
+            }
+            CommandKind::NoHighlight => {
+                editor.buffer.search.highlight = false;
+                Ok(())
+            }
+            CommandKind::Substitute => {
+                let range = match self.range {
+                    Some(range) => range.resolve(editor)?,
+                    None => (editor.buffer.cursor.y, editor.buffer.cursor.y),
+                };
+                editor.substitute(self.arg.as_deref().unwrap_or_default(), range)
+            }
+            CommandKind::Buffer => match self.arg.as_deref() {
+                Some(arg) => {
+                    let id = editor.find_buffer(arg)?;
+                    editor.switch_buffer(id)
+                }
+                None => Ok(()),
+            },
+            CommandKind::BufferNext => {
+                let count = self.count_arg()?.unwrap_or(1);
+                editor.cycle_buffer(count as isize)
+            }
+            CommandKind::BufferPrev => {
+                let count = self.count_arg()?.unwrap_or(1);
+                editor.cycle_buffer(-(count as isize))
+            }
+            CommandKind::BufferDelete => {
+                let id = match self.arg.as_deref() {
+                    Some(arg) => editor.find_buffer(arg)?,
+                    None => editor.buffer.id,
+                };
+                editor.delete_buffer(id, self.bang)
+            }
+            CommandKind::BufferList => {
+                editor.open_buffer_switcher();
+                Ok(())
+            }
+            CommandKind::Split => editor.split_window(self.split_direction(), self.arg.as_deref()),
+            CommandKind::New => editor.new_window(self.split_direction()),
+            CommandKind::Close => editor.close_window(),
+            CommandKind::Only => {
+                editor.only_window();
+                Ok(())
+            }
+            CommandKind::Resize => {
+                let arg = self.arg.as_deref().unwrap_or_default();
+                let invalid = || anyhow!("E474: Invalid argument: {}", arg);
+                let (size, delta) = match arg.chars().next() {
+                    // Without a size the window takes as much space as it can
+                    None => (None, isize::MAX / 2),
+                    Some('+') => (None, arg[1..].parse::<isize>().map_err(|_| invalid())?),
+                    Some('-') => (None, -arg[1..].parse::<isize>().map_err(|_| invalid())?),
+                    Some(_) => (Some(arg.parse().map_err(|_| invalid())?), 0),
+                };
+                editor.resize_window(self.split_direction(), size, delta);
+                Ok(())
+            }
+            CommandKind::WindowCommand => {
+                let arg = self.arg.as_deref().unwrap_or_default();
+                let mut chars = arg.chars();
+                match (chars.next(), chars.next()) {
+                    (Some(key), None) => editor.window_command(key, None),
+                    _ => Err(anyhow!("E474: Invalid argument: {}", arg)),
+                }
+            }
+            CommandKind::QuitAll => editor.quit_all(self.bang),
+            CommandKind::TabNew => editor.new_tab(self.arg.as_deref()),
+            CommandKind::TabClose => {
+                let idx = self.tab_arg()?;
+                editor.close_tab(idx)
+            }
+            CommandKind::TabOnly => editor.only_tab(),
+            // With a count :tabnext goes to that tab page, :tabprevious goes back that many
+            CommandKind::TabNext => match self.tab_arg()? {
+                Some(idx) => editor.goto_tab(idx),
+                None => editor.cycle_tab(1),
+            },
+            CommandKind::TabPrev => {
+                let count = self.count_arg()?.unwrap_or(1);
+                editor.cycle_tab(-(count as isize))
+            }
+            CommandKind::TabFirst => editor.goto_tab(0),
+            CommandKind::TabLast => editor.goto_tab(editor.tabs.len() - 1),
+            CommandKind::TabMove => {
+                let arg = self.arg.as_deref().unwrap_or_default();
+                let invalid = || anyhow!("E474: Invalid argument: {}", arg);
+                let current = editor.tab_idx as isize;
+                let idx = match arg.chars().next() {
+                    None => isize::MAX,
+                    Some('+') => current + arg[1..].parse::<isize>().map_err(|_| invalid())?,
+                    Some('-') => current - arg[1..].parse::<isize>().map_err(|_| invalid())?,
+                    // After tab page N, counted before the move
+                    Some(_) => {
+                        let after = arg.parse::<isize>().map_err(|_| invalid())?;
+                        if after > current {
+                            after - 1
+                        } else {
+                            after
+                        }
+                    }
+                };
+                editor.move_tab(idx.max(0) as usize);
+                Ok(())
+            }
+            CommandKind::Registers => {
+                editor.show_registers(self.arg.as_deref());
+                Ok(())
+            }
+            CommandKind::Marks => {
+                editor.show_marks(self.arg.as_deref());
+                Ok(())
+            }
+            CommandKind::Yank => {
+                let (mut first, mut last) = match self.range {
+                    Some(range) => range.resolve(editor)?,
+                    None => (editor.buffer.cursor.y, editor.buffer.cursor.y),
+                };
+                let (register, count) = self.register_arg()?;
+                // A count yanks that many lines from the last line of the range
+                if let Some(count) = count {
+                    first = last;
+                    last = (first + count.max(1) - 1).min(editor.buffer.text.len_lines() - 1);
+                }
+                editor.yank_lines(first, last, register)
+            }
+            // Never constructed, parse applies the modifier to the following command
+            CommandKind::Vertical => Ok(()),
+            CommandKind::Goto => {
+                if let Some(range) = self.range {
+                    let (_, line) = range.resolve(editor)?;
+                    editor.push_jump();
+                    editor.buffer.cursor = (editor.buffer.first_non_blank(line), line).into();
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Ten lines with the cursor on the fourth
+    fn editor() -> Editor {
+        let mut editor = Editor::new();
+        editor.buffer.text.insert(0, &["line"; 10].join("\n"));
+        editor.buffer.cursor = (0, 3).into();
+        editor
+    }
+
+    fn range(input: &str) -> Result<(usize, usize)> {
+        let command = parse(input)?;
+        command.range.expect("a range").resolve(&editor())
+    }
+
+    fn error(result: Result<impl std::fmt::Debug>) -> String {
+        result.expect_err("an error").to_string()
+    }
+
+    #[test]
+    fn addresses() {
+        assert_eq!(range("3").unwrap(), (2, 2));
+        assert_eq!(range(".").unwrap(), (3, 3));
+        assert_eq!(range("$").unwrap(), (9, 9));
+        assert_eq!(range(".+2").unwrap(), (5, 5));
+        assert_eq!(range("$-1-1").unwrap(), (7, 7));
+        assert_eq!(range("+").unwrap(), (4, 4));
+        assert_eq!(range("--").unwrap(), (1, 1));
+        assert_eq!(range("0").unwrap(), (0, 0));
+    }
+
+    #[test]
+    fn ranges() {
+        assert_eq!(range("%s/a/b/").unwrap(), (0, 9));
+        assert_eq!(range("2,5s/a/b/").unwrap(), (1, 4));
+        assert_eq!(range(",5y").unwrap(), (3, 4));
+        assert_eq!(range("5,y").unwrap(), (3, 4));
+        assert_eq!(range(".,.+3s/a/b/").unwrap(), (3, 6));
+        // Backwards ranges are swapped
+        assert_eq!(range("$,1y").unwrap(), (0, 9));
+        assert_eq!(range("0,$y").unwrap(), (0, 9));
+    }
+
+    #[test]
+    fn invalid_ranges() {
+        assert_eq!(error(range("11")), "E16: Invalid range");
+        assert_eq!(error(range(".-5")), "E16: Invalid range");
+        assert_eq!(error(range("'a")), "E20: Mark not set");
+        assert_eq!(error(parse("'")), "E20: Mark not set");
+        assert_eq!(error(parse("3q")), "E481: No range allowed");
+    }
+
+    #[test]
+    fn huge_addresses() {
+        assert_eq!(
+            error(parse(".+99999999999999999999s")),
+            "E16: Invalid range"
+        );
+        assert_eq!(error(parse("99999999999999999999")), "E16: Invalid range");
+        assert_eq!(
+            error(parse(".+9223372036854775807+1")),
+            "E16: Invalid range"
+        );
+        assert_eq!(
+            error(parse(".-9223372036854775807-2")),
+            "E16: Invalid range"
+        );
+        assert_eq!(error(range("18446744073709551615")), "E16: Invalid range");
+        assert_eq!(error(range("9223372036854775807+1")), "E16: Invalid range");
+        assert_eq!(error(range(".+9223372036854775807")), "E16: Invalid range");
+    }
+
+    #[test]
+    fn command_names() {
+        let command = parse(":  wq!").unwrap();
+        assert_eq!((command.kind, command.bang), (CommandKind::WriteQuit, true));
+        assert_eq!(parse("sav x").unwrap().arg.as_deref(), Some("x"));
+        assert!(parse("vert new").unwrap().vertical);
+        assert_eq!(parse("5").unwrap().kind, CommandKind::Goto);
+        assert_eq!(error(parse("q x")), "E488: Trailing characters: x");
+        assert_eq!(
+            error(parse("nosuch")),
+            "E492: Not an editor command: nosuch"
+        );
+    }
+}
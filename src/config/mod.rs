@@ -1,2 +1,4 @@
 mod settings;
+mod theme;
 pub use settings::*;
+pub use theme::*;
@@ -1,5 +1,8 @@
 use serde::{Deserialize, Serialize};
 
+use super::ThemeSettings;
+use crate::word::IsKeyword;
+
 #[derive(Clone, Copy, Deserialize, Serialize)]
 pub enum TabType {
     Spaces(usize),
@@ -12,11 +15,37 @@ impl Default for TabType {
     }
 }
 
+// Where the + and * registers go. In config.toml e.g. `clipboard = "Osc52"` or
+// `clipboard = { Command = { copy = ["wl-copy"], paste = ["wl-paste", "-n"] } }`.
+#[derive(Clone, Default, Deserialize, Serialize)]
+pub enum ClipboardProvider {
+    // Kept inside the editor only
+    #[default]
+    Internal,
+    // Sent to the terminal as an OSC 52 escape sequence, which also works over SSH. Inside tmux
+    // it needs `set -g allow-passthrough on` to get through to the terminal. Terminals don't let
+    // it be read back, so pasting gives the last copy.
+    Osc52,
+    // Programs the text is piped to and read from, e.g. xclip or pbcopy and pbpaste. Without a
+    // paste command pasting gives the last copy.
+    Command {
+        copy: Vec<String>,
+        #[serde(default)]
+        paste: Vec<String>,
+    },
+}
+
 #[derive(Clone, Deserialize, Serialize)]
 #[serde(default)]
 pub struct Settings {
     pub line_numbers: bool,
     pub tab_type: TabType,
+    pub tab_width: usize,
+    pub clipboard: ClipboardProvider,
+    // Characters that make up words, e.g. `iskeyword = "@,48-57,_,-"`, see IsKeyword. A language
+    // can set its own.
+    pub iskeyword: IsKeyword,
+    pub theme: ThemeSettings,
 }
 
 impl Default for Settings {
@@ -24,6 +53,10 @@ impl Default for Settings {
         Self {
             line_numbers: true,
             tab_type: TabType::default(),
+            tab_width: 4,
+            clipboard: ClipboardProvider::default(),
+            iskeyword: IsKeyword::default(),
+            theme: ThemeSettings::default(),
         }
     }
 }
@@ -0,0 +1,287 @@
+use std::collections::HashMap;
+
+use anyhow::{anyhow, Result};
+use ratatui::style::{Color, Modifier, Style, Stylize};
+use serde::{Deserialize, Serialize};
+
+// Style as written in config.toml, every field is optional so a single color can be
+// overridden. Modifiers use ratatui's names, e.g. `add_modifier = "BOLD | ITALIC"`.
+#[derive(Clone, Copy, Default, Deserialize, Serialize)]
+#[serde(default)]
+pub struct StyleDef {
+    pub fg: Option<Color>,
+    pub bg: Option<Color>,
+    pub add_modifier: Modifier,
+    pub sub_modifier: Modifier,
+}
+
+impl From<StyleDef> for Style {
+    fn from(def: StyleDef) -> Self {
+        let mut style = Style::default()
+            .add_modifier(def.add_modifier)
+            .remove_modifier(def.sub_modifier);
+        style.fg = def.fg;
+        style.bg = def.bg;
+        style
+    }
+}
+
+// The `[theme]` table: a named theme plus per element overrides patched on top of it
+#[derive(Clone, Deserialize, Serialize)]
+#[serde(default)]
+pub struct ThemeSettings {
+    pub name: String,
+    pub text: Option<StyleDef>,
+    pub gutter: Option<StyleDef>,
+    pub statusline: Option<StyleDef>,
+    pub statusline_inactive: Option<StyleDef>,
+    pub tabline: Option<StyleDef>,
+    pub tabline_selected: Option<StyleDef>,
+    pub selection: Option<StyleDef>,
+    pub search_match: Option<StyleDef>,
+    pub current_match: Option<StyleDef>,
+    pub cursor_line: Option<StyleDef>,
+    pub popup: Option<StyleDef>,
+    pub scopes: HashMap<String, StyleDef>,
+}
+
+impl Default for ThemeSettings {
+    fn default() -> Self {
+        Self {
+            name: "default".to_string(),
+            text: None,
+            gutter: None,
+            statusline: None,
+            statusline_inactive: None,
+            tabline: None,
+            tabline_selected: None,
+            selection: None,
+            search_match: None,
+            current_match: None,
+            cursor_line: None,
+            popup: None,
+            scopes: HashMap::new(),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct Theme {
+    pub text: Style,
+    pub gutter: Style,
+    pub statusline: Style,
+    // Status lines of the other windows and the separators between them
+    pub statusline_inactive: Style,
+    // Labels of the other tab pages and the rest of the tabline
+    pub tabline: Style,
+    pub tabline_selected: Style,
+    pub selection: Style,
+    pub search_match: Style,
+    // Match being confirmed by :s///c
+    pub current_match: Style,
+    pub cursor_line: Style,
+    pub popup: Style,
+    pub scopes: HashMap<String, Style>,
+}
+
+pub const THEMES: &[&str] = &["default", "gruvbox", "solarized-light"];
+
+impl Default for Theme {
+    fn default() -> Self {
+        let style = Style::default();
+        Self {
+            text: style,
+            gutter: style.dark_gray(),
+            statusline: style.reversed(),
+            statusline_inactive: style.dark_gray().reversed(),
+            tabline: style.dark_gray().reversed(),
+            tabline_selected: style.bold(),
+            selection: style.reversed(),
+            search_match: style.black().on_yellow(),
+            current_match: style.black().on_red(),
+            cursor_line: style,
+            popup: style,
+            scopes: scopes(&[
+                ("comment", style.dark_gray().italic()),
+                ("string", style.green()),
+                ("keyword", style.magenta()),
+                ("type", style.yellow()),
+                ("function", style.blue()),
+                ("number", style.cyan()),
+                ("constant", style.cyan()),
+                ("attribute", style.light_yellow()),
+                ("property", style.light_blue()),
+                ("variable", style.light_red()),
+                ("special", style.light_magenta()),
+                ("punctuation", style.gray()),
+                ("heading", style.blue().bold()),
+                ("strong", style.bold()),
+                ("emphasis", style.italic()),
+                ("link", style.light_blue().underlined()),
+            ]),
+        }
+    }
+}
+
+fn scopes(styles: &[(&str, Style)]) -> HashMap<String, Style> {
+    styles
+        .iter()
+        .map(|(scope, style)| (scope.to_string(), *style))
+        .collect()
+}
+
+fn gruvbox() -> Theme {
+    let fg = Color::Rgb(0xeb, 0xdb, 0xb2);
+    let bg = Color::Rgb(0x28, 0x28, 0x28);
+    let bg1 = Color::Rgb(0x3c, 0x38, 0x36);
+    let bg2 = Color::Rgb(0x50, 0x49, 0x45);
+    let gray = Color::Rgb(0x92, 0x83, 0x74);
+    let red = Color::Rgb(0xfb, 0x49, 0x34);
+    let green = Color::Rgb(0xb8, 0xbb, 0x26);
+    let yellow = Color::Rgb(0xfa, 0xbd, 0x2f);
+    let blue = Color::Rgb(0x83, 0xa5, 0x98);
+    let purple = Color::Rgb(0xd3, 0x86, 0x9b);
+    let aqua = Color::Rgb(0x8e, 0xc0, 0x7c);
+    let orange = Color::Rgb(0xfe, 0x80, 0x19);
+
+    let style = Style::default();
+    Theme {
+        text: style.fg(fg).bg(bg),
+        gutter: style.fg(gray).bg(bg),
+        statusline: style.fg(fg).bg(bg2),
+        statusline_inactive: style.fg(gray).bg(bg1),
+        tabline: style.fg(gray).bg(bg1),
+        tabline_selected: style.fg(fg).bg(bg).bold(),
+        selection: style.bg(bg2),
+        search_match: style.fg(bg).bg(yellow),
+        current_match: style.fg(bg).bg(orange),
+        cursor_line: style.bg(bg1),
+        popup: style.fg(fg).bg(bg1),
+        scopes: scopes(&[
+            ("comment", style.fg(gray).italic()),
+            ("string", style.fg(green)),
+            ("keyword", style.fg(red)),
+            ("type", style.fg(yellow)),
+            ("function", style.fg(green).bold()),
+            ("number", style.fg(purple)),
+            ("constant", style.fg(purple)),
+            ("attribute", style.fg(aqua)),
+            ("property", style.fg(blue)),
+            ("variable", style.fg(blue)),
+            ("special", style.fg(orange)),
+            ("punctuation", style.fg(gray)),
+            ("heading", style.fg(green).bold()),
+            ("strong", style.bold()),
+            ("emphasis", style.italic()),
+            ("link", style.fg(aqua).underlined()),
+        ]),
+    }
+}
+
+fn solarized_light() -> Theme {
+    let base00 = Color::Rgb(0x65, 0x7b, 0x83);
+    let base1 = Color::Rgb(0x93, 0xa1, 0xa1);
+    let base2 = Color::Rgb(0xee, 0xe8, 0xd5);
+    let base3 = Color::Rgb(0xfd, 0xf6, 0xe3);
+    let yellow = Color::Rgb(0xb5, 0x89, 0x00);
+    let orange = Color::Rgb(0xcb, 0x4b, 0x16);
+    let red = Color::Rgb(0xdc, 0x32, 0x2f);
+    let magenta = Color::Rgb(0xd3, 0x36, 0x82);
+    let violet = Color::Rgb(0x6c, 0x71, 0xc4);
+    let blue = Color::Rgb(0x26, 0x8b, 0xd2);
+    let cyan = Color::Rgb(0x2a, 0xa1, 0x98);
+    let green = Color::Rgb(0x85, 0x99, 0x00);
+
+    let style = Style::default();
+    Theme {
+        text: style.fg(base00).bg(base3),
+        gutter: style.fg(base1).bg(base2),
+        statusline: style.fg(base3).bg(base00),
+        statusline_inactive: style.fg(base1).bg(base2),
+        tabline: style.fg(base1).bg(base2),
+        tabline_selected: style.fg(base00).bg(base3).bold(),
+        selection: style.bg(base2).reversed(),
+        search_match: style.fg(base3).bg(yellow),
+        current_match: style.fg(base3).bg(red),
+        cursor_line: style.bg(base2),
+        popup: style.fg(base00).bg(base2),
+        scopes: scopes(&[
+            ("comment", style.fg(base1).italic()),
+            ("string", style.fg(cyan)),
+            ("keyword", style.fg(green)),
+            ("type", style.fg(yellow)),
+            ("function", style.fg(blue)),
+            ("number", style.fg(magenta)),
+            ("constant", style.fg(magenta)),
+            ("attribute", style.fg(violet)),
+            ("property", style.fg(blue)),
+            ("variable", style.fg(orange)),
+            ("special", style.fg(red)),
+            ("punctuation", style.fg(base1)),
+            ("heading", style.fg(orange).bold()),
+            ("strong", style.bold()),
+            ("emphasis", style.italic()),
+            ("link", style.fg(violet).underlined()),
+        ]),
+    }
+}
+
+impl Theme {
+    pub fn named(name: &str) -> Option<Self> {
+        match name {
+            "default" => Some(Self::default()),
+            "gruvbox" => Some(gruvbox()),
+            "solarized-light" => Some(solarized_light()),
+            _ => None,
+        }
+    }
+
+    pub fn from_settings(settings: &ThemeSettings) -> Result<Self> {
+        let mut theme = Self::named(&settings.name).ok_or_else(|| {
+            anyhow!(
+                "Unknown theme: {} (available: {})",
+                settings.name,
+                THEMES.join(", ")
+            )
+        })?;
+
+        let overrides = [
+            (&mut theme.text, settings.text),
+            (&mut theme.gutter, settings.gutter),
+            (&mut theme.statusline, settings.statusline),
+            (&mut theme.statusline_inactive, settings.statusline_inactive),
+            (&mut theme.tabline, settings.tabline),
+            (&mut theme.tabline_selected, settings.tabline_selected),
+            (&mut theme.selection, settings.selection),
+            (&mut theme.search_match, settings.search_match),
+            (&mut theme.current_match, settings.current_match),
+            (&mut theme.cursor_line, settings.cursor_line),
+            (&mut theme.popup, settings.popup),
+        ];
+        for (style, def) in overrides {
+            if let Some(def) = def {
+                *style = style.patch(def);
+            }
+        }
+        for (scope, def) in &settings.scopes {
+            let style = theme.scopes.entry(scope.clone()).or_default();
+            *style = style.patch(*def);
+        }
+        Ok(theme)
+    }
+
+    // Dotted scopes fall back to their parents, so `keyword.control` uses `keyword` unless the
+    // theme styles it separately
+    pub fn scope(&self, scope: &str) -> Style {
+        let mut scope = scope;
+        loop {
+            if let Some(style) = self.scopes.get(scope) {
+                return *style;
+            }
+            match scope.rfind('.') {
+                Some(idx) => scope = &scope[..idx],
+                None => return Style::default(),
+            }
+        }
+    }
+}
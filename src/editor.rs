@@ -1,21 +1,40 @@
-use std::{fs, path::PathBuf};
+use std::{collections::HashMap, path::PathBuf};
 
 use anyhow::{anyhow, Result};
-use itertools::Itertools;
-use ratatui::{prelude::*, widgets::*};
+use ratatui::prelude::*;
+use unicode_segmentation::UnicodeSegmentation;
 
 use crate::{
-    config::{Settings, TabType},
+    buffer::Buffer,
+    command,
+    config::{Settings, TabType, Theme},
     cursor::Cursor,
-    search::Search,
-    util::is_executable,
-    word,
+    history::Edit,
+    macros::{self, Macros},
+    mark::{self, Jump, JumpList},
+    normal::{Action, InsertAt, NormalCommand, Operator, OperatorRange, Target},
+    register::{Register, RegisterKind, Registers},
+    repeat::ChangeRecorder,
+    search::{SearchDirection, SearchResult},
+    selection::{block_span, BlockInsert, Selection, VisualKind},
+    substitute::{self, Substitute, SubstituteState},
+    syntax::LanguageRegistry,
+    text_object,
+    unicode::{col_at_display, display_col, grapheme_count, grapheme_to_byte, grapheme_width},
+    window::{neighbour, SplitDirection, TabPage, Window, WindowDirection, WindowNode},
+    word::{self, IsKeyword},
 };
 
 const MEDIUM_SCROLL: usize = 19;
 
-const COMMAND_HISTORY_MAX: usize = 100;
+// Entries kept in each of the command line and search histories
+const HISTORY_MAX: usize = 100;
 
+// Longest text a count repeats something into, 999999999p stops there instead of using up all
+// the memory
+const MAX_REPEAT_LEN: usize = 1 << 20;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
 pub enum CursorMove {
     Up,
     Down,
@@ -26,8 +45,90 @@ pub enum CursorMove {
     WordStartForward,
     WordStartBackward,
     WordEndForward,
+    WordEndBackward,
+    // W, B, E and gE, for WORDs
+    BigWordStartForward,
+    BigWordStartBackward,
+    BigWordEndForward,
+    BigWordEndBackward,
     Start,
     End,
+    // `x and 'x, to mark x or to its line
+    Mark(char),
+    MarkLine(char),
+    // f, F, t and T with the character to find on the line, t and T stop next to it
+    FindForward(char),
+    FindBackward(char),
+    TillForward(char),
+    TillBackward(char),
+    // ; repeats the last of those, and , repeats it in the other direction
+    RepeatFind,
+    RepeatFindReverse,
+}
+
+// How an operator treats the text between the cursor and the end of a motion
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum MotionKind {
+    Exclusive,
+    Inclusive,
+    Linewise,
+}
+
+impl CursorMove {
+    pub fn kind(&self) -> MotionKind {
+        match self {
+            CursorMove::Up
+            | CursorMove::Down
+            | CursorMove::Start
+            | CursorMove::End
+            | CursorMove::MarkLine(_) => MotionKind::Linewise,
+            CursorMove::LineEnd
+            | CursorMove::WordEndForward
+            | CursorMove::WordEndBackward
+            | CursorMove::BigWordEndForward
+            | CursorMove::BigWordEndBackward
+            | CursorMove::FindForward(_)
+            | CursorMove::TillForward(_) => MotionKind::Inclusive,
+            _ => MotionKind::Exclusive,
+        }
+    }
+
+    // Motions that fail when they can't move, e.g. j on the last line, unlike 0 or G. A find
+    // fails when the character isn't there, see Editor::find_target.
+    pub fn can_fail(&self) -> bool {
+        !matches!(
+            self,
+            CursorMove::LineBegin
+                | CursorMove::LineEnd
+                | CursorMove::Start
+                | CursorMove::End
+                | CursorMove::Mark(_)
+                | CursorMove::MarkLine(_)
+        ) && !self.is_find()
+    }
+
+    pub fn is_find(&self) -> bool {
+        matches!(
+            self,
+            CursorMove::FindForward(_)
+                | CursorMove::FindBackward(_)
+                | CursorMove::TillForward(_)
+                | CursorMove::TillBackward(_)
+                | CursorMove::RepeatFind
+                | CursorMove::RepeatFindReverse
+        )
+    }
+
+    // The find going the other way, for ,
+    fn reversed(self) -> Self {
+        match self {
+            CursorMove::FindForward(c) => CursorMove::FindBackward(c),
+            CursorMove::FindBackward(c) => CursorMove::FindForward(c),
+            CursorMove::TillForward(c) => CursorMove::TillBackward(c),
+            CursorMove::TillBackward(c) => CursorMove::TillForward(c),
+            motion => motion,
+        }
+    }
 }
 
 #[derive(Default)]
@@ -35,423 +136,2665 @@ pub enum CurrentScreen {
     #[default]
     Editing,
     Exiting,
+    Substitute,
+    BufferSwitcher,
+    // :registers, the argument limits which ones are listed
+    Registers(Option<String>),
+    // :marks, likewise
+    Marks(Option<String>),
+}
+
+#[derive(Clone, Copy, Default, PartialEq, Eq)]
+pub enum Mode {
+    #[default]
+    Normal,
+    Insert,
+    Visual(VisualKind),
+    Command,
+    Search,
+}
+
+impl Mode {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Mode::Normal => "NORMAL",
+            Mode::Insert => "INSERT",
+            Mode::Visual(VisualKind::Char) => "VISUAL",
+            Mode::Visual(VisualKind::Line) => "VISUAL LINE",
+            Mode::Visual(VisualKind::Block) => "VISUAL BLOCK",
+            Mode::Command => "COMMAND",
+            Mode::Search => "SEARCH",
+        }
+    }
 }
 
 #[derive(Default)]
 pub struct Editor {
-    pub lines: Vec<String>,
-    pub cursor: Cursor,
-    // TODO: Make this absolute path
-    pub filename: Option<PathBuf>,
-    pub scroll: (u16, u16),
-    pub search: Search,
+    pub buffer: Buffer,
+    // Buffers other than the current one, in the order they were opened
+    pub buffers: Vec<Buffer>,
+    next_buffer_id: usize,
+    pub buffer_switcher_idx: usize,
+    pub windows: Vec<Window>,
+    pub layout: WindowNode,
+    pub window_id: usize,
+    next_window_id: usize,
+    // Where each window was last drawn, for moving between and resizing windows
+    pub window_areas: Vec<(usize, Rect)>,
+    // Every tab page, the current one is `tabs[tab_idx]`
+    pub tabs: Vec<TabPage>,
+    pub tab_idx: usize,
+    // Keys of a normal mode command that isn't complete yet, e.g. `2d`
+    pub pending_keys: String,
+    pub registers: Registers,
+    // Text typed since entering insert mode, for the ". register
+    pub inserted: String,
+    // How Insert mode was started and the count given, the text typed goes in count times
+    pub insert_repeat: Option<(InsertAt, usize)>,
+    // The last change, repeated by `.`
+    pub repeat: ChangeRecorder,
+    pub macros: Macros,
+    // Set by a command that fails, like a motion that can't move, which stops the macros being
+    // played
+    pub bell: bool,
+    pub jumps: JumpList,
+    // The last f, F, t or T, for ; and ,
+    pub last_find: Option<CursorMove>,
+    // Global marks of files whose buffer was deleted, set again when the file is opened
+    pub file_marks: HashMap<char, (PathBuf, Cursor)>,
+    pub search_origin: Option<(Cursor, String)>,
+    // The count typed before / or ?, which match the search goes to
+    pub search_count: usize,
+    pub search_history: Vec<String>,
+    pub last_substitute: Option<Substitute>,
+    pub substitute_state: Option<SubstituteState>,
     pub status_message: String,
     pub running: bool,
     pub current_screen: CurrentScreen,
+    pub mode: Mode,
+    pub visual_anchor: Cursor,
+    // $ in Visual Block mode, see Selection
+    pub visual_to_end: bool,
+    pub block_insert: Option<BlockInsert>,
     pub command: String,
     pub command_x: usize,
     pub command_history: Vec<String>,
     pub command_history_idx: usize,
+    // Lines CTRL-D and CTRL-U scroll, set by giving them a count
+    pub scroll_amount: Option<usize>,
     pub settings: Settings,
+    pub theme: Theme,
+    pub languages: LanguageRegistry,
 }
 
 impl Editor {
     pub fn new() -> Self {
         Self {
-            lines: vec!["".to_string()],
+            buffer: Buffer::new(1),
+            next_buffer_id: 2,
+            windows: vec![Window {
+                id: 1,
+                buffer_id: 1,
+                cursor: Cursor::default(),
+                scroll: (0, 0),
+                preferred_col: None,
+            }],
+            layout: WindowNode::Leaf(1),
+            window_id: 1,
+            tabs: vec![TabPage {
+                layout: WindowNode::Leaf(1),
+                window_id: 1,
+            }],
+            next_window_id: 2,
             running: true,
             ..Default::default()
         }
     }
 
+    // Switches to the buffer already holding `path`, otherwise loads it into a new one
     pub fn open(&mut self, path: &str) -> Result<()> {
         let path = PathBuf::from(path);
-        if path.exists() && !path.is_file() {
-            return Err(anyhow!("Path is not file"));
-        }
-        if path.exists() && is_executable(&path)? {
-            return Err(anyhow!("Cannot open executable"));
+        let existing = self
+            .buffer_list()
+            .into_iter()
+            .find(|buffer| buffer.filename.as_ref() == Some(&path))
+            .map(|buffer| buffer.id);
+        if let Some(id) = existing {
+            return self.switch_buffer(id);
         }
 
-        let file_content = fs::read_to_string(&path)?;
-        let lines = file_content
-            .split('\n')
-            .map(|s| s.to_string())
-            .collect_vec();
-        self.lines = lines;
-        self.filename = Some(path);
+        let mut buffer = Buffer::open(self.next_buffer_id, path.clone(), &self.languages)?;
+        self.next_buffer_id += 1;
+        // Global marks kept from an earlier buffer of the file come back with it
+        self.file_marks
+            .retain(|name, (file, cursor)| match *file == path {
+                true => {
+                    buffer.marks.insert(*name, *cursor);
+                    false
+                }
+                false => true,
+            });
+        self.jumps.push(self.current_jump());
+        self.set_buffer(buffer);
         Ok(())
     }
 
-    pub fn save(&mut self) -> Result<()> {
-        if self.filename.is_none() {
-            self.status_message = "Filename not set".to_string();
-            return Err(anyhow!("Filename not set"));
+    // Makes `buffer` the current one, shown in the current window
+    fn set_buffer(&mut self, buffer: Buffer) {
+        let previous = std::mem::replace(&mut self.buffer, buffer);
+        let shown = self
+            .windows
+            .iter()
+            .any(|window| window.id != self.window_id && window.buffer_id == previous.id);
+        // An untouched [No Name] buffer is dropped instead of lingering in the list
+        if shown || !previous.is_pristine() {
+            self.buffers.push(previous);
+            self.buffers.sort_by_key(|buffer| buffer.id);
+        } else {
+            self.jumps.remove_buffer(previous.id);
+        }
+        let buffer_id = self.buffer.id;
+        let window = self.current_window_mut();
+        if window.buffer_id != buffer_id {
+            window.buffer_id = buffer_id;
+            window.preferred_col = None;
         }
-
-        let path = self.filename.as_ref().unwrap();
-        let contents = self.lines.join("\n");
-
-        fs::write(path, contents)?;
-
-        self.status_message = format!("\"{}\" written", path.display());
-        Ok(())
     }
 
-    pub fn widget(&mut self) -> impl Widget + '_ {
-        Renderer::new(self)
+    fn new_buffer(&mut self) -> Buffer {
+        self.next_buffer_id += 1;
+        Buffer::new(self.next_buffer_id - 1)
     }
 
-    pub fn insert_char_at_cursor(&mut self, c: char) {
-        if let Some(line) = self.lines.get_mut(self.cursor.y) {
-            if self.cursor.x == line.len() {
-                line.push(c);
-            } else {
-                line.insert(self.cursor.x, c);
+    // With `rename`, as for :saveas, the buffer takes the new name, otherwise only a buffer
+    // without one does
+    pub fn write(&mut self, path: Option<&str>, force: bool, rename: bool) -> Result<()> {
+        let path = match path {
+            Some(path) => {
+                let path = PathBuf::from(path);
+                if !force && path.exists() && self.buffer.filename.as_ref() != Some(&path) {
+                    return Err(anyhow!("E13: File exists (add ! to override)"));
+                }
+                if self.buffer.filename.is_none() || rename {
+                    self.buffer.filename = Some(path.clone());
+                    self.buffer.detect_language(&self.languages);
+                }
+                path
             }
+            None => self
+                .buffer
+                .filename
+                .clone()
+                .ok_or_else(|| anyhow!("E32: No file name"))?,
+        };
 
-            self.move_cursor(CursorMove::Right);
+        self.buffer.text.write_to_file(&path)?;
+        if self.buffer.filename.as_ref() == Some(&path) {
+            self.buffer.saved_revision = self.buffer.history.current();
         }
+
+        self.status_message = format!(
+            "\"{}\" {}L written",
+            path.display(),
+            self.buffer.text.len_lines()
+        );
+        Ok(())
     }
 
-    pub fn backspace_at_cursor(&mut self) {
-        if self.cursor.x == 0 {
-            if self.cursor.y == 0 {
-                return;
+    // :e with a path opens it alongside the other buffers, without one it reloads the current
+    // file from disk
+    pub fn edit(&mut self, path: Option<&str>, force: bool) -> Result<()> {
+        match path {
+            Some(path) => self.open(path)?,
+            None => {
+                if !force && self.buffer.is_dirty() {
+                    return Err(anyhow!(
+                        "E37: No write since last change (add ! to override)"
+                    ));
+                }
+                let path = self
+                    .buffer
+                    .filename
+                    .clone()
+                    .ok_or_else(|| anyhow!("E32: No file name"))?;
+                let marks = std::mem::take(&mut self.buffer.marks);
+                self.buffer = Buffer::open(self.buffer.id, path, &self.languages)?;
+                self.buffer.marks = marks;
             }
+        }
+        self.status_message = self.buffer_info();
+        Ok(())
+    }
 
-            let line = self.lines.remove(self.cursor.y);
-            if let Some(prev_line) = self.lines.get_mut(self.cursor.y - 1) {
-                let join_idx = prev_line.len();
-                prev_line.push_str(&line);
-                self.move_cursor(CursorMove::Up);
-                self.cursor.x = join_idx;
-            }
+    fn buffer_info(&self) -> String {
+        format!(
+            "\"{}\" {}L",
+            self.buffer.name(),
+            self.buffer.text.len_lines()
+        )
+    }
 
-            return;
+    // Every buffer including the current one, ordered by id
+    pub fn buffer_list(&self) -> Vec<&Buffer> {
+        let mut list: Vec<&Buffer> = self.buffers.iter().collect();
+        list.push(&self.buffer);
+        list.sort_by_key(|buffer| buffer.id);
+        list
+    }
+
+    pub fn switch_buffer(&mut self, id: usize) -> Result<()> {
+        if id == self.buffer.id {
+            return Ok(());
         }
 
-        if let Some(line) = self.lines.get_mut(self.cursor.y) {
-            if self.cursor.x == line.len() {
-                line.pop();
-            } else {
-                line.remove(self.cursor.x - 1);
-            }
+        let idx = self
+            .buffers
+            .iter()
+            .position(|buffer| buffer.id == id)
+            .ok_or_else(|| anyhow!("E86: Buffer {} does not exist", id))?;
+        self.jumps.push(self.current_jump());
+        self.show_buffer(idx);
+        self.status_message = self.buffer_info();
+        Ok(())
+    }
 
-            self.move_cursor(CursorMove::Left);
-        }
+    // Makes `self.buffers[idx]` the current buffer
+    fn show_buffer(&mut self, idx: usize) {
+        let buffer = self.buffers.remove(idx);
+        self.set_buffer(buffer);
+        self.clamp_cursor();
     }
 
-    pub fn delete_char_at_cursor(&mut self) {
-        if let Some(line) = self.lines.get_mut(self.cursor.y) {
-            if !line.is_empty() {
-                line.remove(self.cursor.x);
-            }
+    // Moves `offset` buffers along the list, wrapping around at either end
+    pub fn cycle_buffer(&mut self, offset: isize) -> Result<()> {
+        let ids: Vec<usize> = self.buffer_list().iter().map(|buffer| buffer.id).collect();
+        let idx = ids.iter().position(|id| *id == self.buffer.id).unwrap_or(0);
+        let target = (idx as isize + offset).rem_euclid(ids.len() as isize) as usize;
+        self.switch_buffer(ids[target])
+    }
+
+    // Resolves the argument of :buffer and :bdelete, a buffer number or part of a file name
+    pub fn find_buffer(&self, arg: &str) -> Result<usize> {
+        if let Ok(id) = arg.parse::<usize>() {
+            return match self.buffer_list().iter().any(|buffer| buffer.id == id) {
+                true => Ok(id),
+                false => Err(anyhow!("E86: Buffer {} does not exist", id)),
+            };
         }
 
-        if self.char_at(self.cursor.into()).is_none() {
-            self.move_cursor(CursorMove::Left);
+        let matches: Vec<usize> = self
+            .buffer_list()
+            .iter()
+            .filter(|buffer| buffer.filename.is_some() && buffer.name().contains(arg))
+            .map(|buffer| buffer.id)
+            .collect();
+        match matches[..] {
+            [id] => Ok(id),
+            [] => Err(anyhow!("E94: No matching buffer for {}", arg)),
+            _ => Err(anyhow!("E93: More than one match for {}", arg)),
         }
     }
 
-    pub fn delete_line_at_cursor(&mut self) {
-        if self.lines.len() == 1 {
-            self.lines[0].clear();
-            self.move_cursor(CursorMove::LineBegin);
-            return;
+    pub fn delete_buffer(&mut self, id: usize, force: bool) -> Result<()> {
+        let dirty = self
+            .buffer_list()
+            .iter()
+            .any(|buffer| buffer.id == id && buffer.is_dirty());
+        if !force && dirty {
+            return Err(anyhow!(
+                "E89: No write since last change for buffer {} (add ! to override)",
+                id
+            ));
         }
 
-        if self.cursor.y == self.lines.len() - 1 {
-            self.lines.remove(self.cursor.y);
-            self.move_cursor(CursorMove::Up);
-            return;
+        // Its global marks outlive it, and its jumps go
+        if let Some(buffer) = self
+            .buffer_list()
+            .into_iter()
+            .find(|buffer| buffer.id == id)
+        {
+            let marks = buffer.file_marks();
+            self.file_marks.extend(marks);
+        }
+        self.jumps.remove_buffer(id);
+
+        if id == self.buffer.id {
+            // The current buffer is replaced by the next one, or by an empty buffer when it
+            // was the last
+            let next = self
+                .buffers
+                .iter()
+                .position(|buffer| buffer.id > id)
+                .or_else(|| self.buffers.len().checked_sub(1));
+            self.buffer = match next {
+                Some(idx) => self.buffers.remove(idx),
+                None => self.new_buffer(),
+            };
+            self.clamp_cursor();
+            self.status_message = self.buffer_info();
         }
+        self.buffers.retain(|buffer| buffer.id != id);
 
-        self.lines.remove(self.cursor.y);
-        if self.char_at(self.cursor.into()).is_none() {
-            self.move_cursor(CursorMove::LineEnd);
+        // Windows that showed it move to the current buffer
+        let buffer_id = self.buffer.id;
+        for window in &mut self.windows {
+            if window.buffer_id == id || window.id == self.window_id {
+                window.buffer_id = buffer_id;
+                window.cursor = Cursor::default();
+                window.scroll = (0, 0);
+                window.preferred_col = None;
+            }
         }
+        Ok(())
     }
 
-    pub fn newline_above_cursor(&mut self) {
-        self.lines.insert(self.cursor.y, "".to_string());
-        self.move_cursor(CursorMove::LineBegin);
+    pub fn open_buffer_switcher(&mut self) {
+        self.buffer_switcher_idx = self
+            .buffer_list()
+            .iter()
+            .position(|buffer| buffer.id == self.buffer.id)
+            .unwrap_or(0);
+        self.current_screen = CurrentScreen::BufferSwitcher;
     }
 
-    pub fn newline_under_cursor(&mut self) {
-        self.lines.insert(self.cursor.y + 1, "".to_string());
-        self.move_cursor(CursorMove::Down);
+    pub fn is_dirty(&self) -> bool {
+        self.buffer_list().iter().any(|buffer| buffer.is_dirty())
     }
 
-    pub fn newline_at_cursor(&mut self) {
-        if let Some(line) = self.lines.get_mut(self.cursor.y) {
-            let line_clone = line.clone();
-            let (left, right) = line_clone.split_at(self.cursor.x);
-            *line = left.to_string();
-            self.lines.insert(self.cursor.y + 1, right.to_string());
-            self.move_cursor(CursorMove::Down);
-            self.move_cursor(CursorMove::LineBegin);
+    pub fn set_mode(&mut self, mode: Mode) {
+        let visual = matches!(self.mode, Mode::Visual(_));
+        if visual && !matches!(mode, Mode::Visual(_)) {
+            self.buffer.last_visual = self.selection();
         }
-    }
 
-    pub fn insert_tab(&mut self) {
-        if let Some(line) = self.lines.get_mut(self.cursor.y) {
-            match self.settings.tab_type {
-                TabType::Spaces(n) => {
-                    line.insert_str(self.cursor.x, &" ".repeat(n));
-                    self.cursor.x += n;
-                }
-                TabType::Tabs(n) => {
-                    line.insert_str(self.cursor.x, &"\t".repeat(n));
-                    self.cursor.x += n;
-                }
-            }
+        let leaving_insert = self.mode == Mode::Insert && mode != Mode::Insert;
+        if leaving_insert {
+            self.repeat_insert();
+            self.registers.last_insert = std::mem::take(&mut self.inserted);
         }
-    }
 
-    pub fn move_cursor(&mut self, cursor_move: CursorMove) {
-        match cursor_move {
-            // TODO: Implement some sort of column system to mimic vim's vertical movement with
-            // lines of different lengths
-            CursorMove::Up => {
-                let computed = self.cursor.y.saturating_sub(1);
-                self.cursor.y = computed;
-                if self.char_at((self.cursor.x, computed)).is_none() {
-                    self.move_cursor(CursorMove::LineEnd);
-                }
-            }
-            CursorMove::Down => {
-                let computed = self.cursor.y.saturating_add(1);
-                if self.char_at((self.cursor.x, computed)).is_some() {
-                    self.cursor.y = computed;
-                } else if computed < self.lines.len() {
-                    self.cursor.y = computed;
-                    self.move_cursor(CursorMove::LineEnd);
-                }
-            }
-            // XXX: I think this needs to be improved for readability
-            CursorMove::Left => {
-                let computed = self.cursor.x.saturating_sub(1);
-                if self.char_at((computed, self.cursor.y)).is_some() {
-                    self.cursor.x = computed;
-                } else if let Some(line) = self.lines.get(self.cursor.y) {
-                    if computed == line.len() {
-                        self.cursor.x = computed;
-                    }
+        match mode {
+            Mode::Normal => {
+                if self.mode == Mode::Insert {
+                    self.move_cursor(CursorMove::Left);
                 }
             }
-            // XXX: I think this needs to be improved for readability
-            CursorMove::Right => {
-                let computed = self.cursor.x.saturating_add(1);
-                if self.char_at((computed, self.cursor.y)).is_some() {
-                    self.cursor.x = computed;
-                } else if let Some(line) = self.lines.get(self.cursor.y) {
-                    if computed == line.len() {
-                        self.cursor.x = computed;
-                    }
+            // Switching between the kinds of Visual mode keeps the selection
+            Mode::Visual(_) => {
+                if !visual {
+                    self.visual_anchor = self.buffer.cursor;
+                    self.visual_to_end = false;
                 }
             }
-            CursorMove::LineBegin => {
-                self.cursor.x = 0;
+            Mode::Command => {
+                self.command.clear();
+                self.command_x = 0;
+                self.command_history_idx = self.command_history.len();
             }
-            CursorMove::LineEnd => {
-                if let Some(line) = self.lines.get(self.cursor.y) {
-                    self.cursor.x = line.len();
-                }
+            Mode::Search => {
+                self.command.clear();
+                self.command_x = 0;
+                self.command_history_idx = self.search_history.len();
             }
-            // XXX: At some point this should be replaced by a lexer of some sort
-            // TODO: Make this go next line
-            CursorMove::WordStartForward => {
-                if let Some(line) = self.lines.get(self.cursor.y) {
-                    self.cursor.x = match word::find_word_start_forward(line, self.cursor.x) {
-                        Some(idx) => idx,
-                        None => self.cursor.x,
-                    }
-                }
+            Mode::Insert => {}
+        }
+        if leaving_insert {
+            self.finish_block_insert();
+        }
+        self.mode = mode;
+        self.clamp_cursor();
+    }
+
+    // i, a, I, A, o and O
+    pub fn start_insert(&mut self, at: InsertAt, count: usize) {
+        self.set_mode(Mode::Insert);
+        match at {
+            InsertAt::Cursor => {}
+            InsertAt::AfterCursor => self.move_cursor(CursorMove::Right),
+            InsertAt::LineBegin => self.move_cursor(CursorMove::LineBegin),
+            InsertAt::LineEnd => self.move_cursor(CursorMove::LineEnd),
+            InsertAt::LineBelow => self.newline_under_cursor(),
+            InsertAt::LineAbove => self.newline_above_cursor(),
+        }
+        self.insert_repeat = Some((at, count));
+    }
+
+    // Inserts the text typed again for the rest of the count, 3ix<Esc> leaves xxx and 3ox<Esc>
+    // three lines of x
+    fn repeat_insert(&mut self) {
+        let Some((at, count)) = self.insert_repeat.take() else {
+            return;
+        };
+        if count < 2 || self.inserted.is_empty() {
+            return;
+        }
+        let (pos, text) = match at {
+            InsertAt::LineBelow | InsertAt::LineAbove => {
+                let y = self.buffer.cursor.y;
+                let pos = (self.buffer.text.line_len(y), y).into();
+                (pos, format!("\n{}", self.inserted))
             }
-            CursorMove::WordStartBackward => {
-                if let Some(line) = self.lines.get(self.cursor.y) {
-                    self.cursor.x = match word::find_word_start_backward(line, self.cursor.x) {
-                        Some(idx) => idx,
-                        None => self.cursor.x,
-                    }
-                }
+            _ => (self.buffer.cursor, self.inserted.clone()),
+        };
+        let count = repeat_count(&text, count - 1);
+        self.buffer.cursor = self.buffer.insert_text(pos, &text.repeat(count));
+    }
+
+    // v, V and CTRL-V start Visual mode of their kind, switch to it from another kind, or end it
+    pub fn toggle_visual(&mut self, kind: VisualKind) {
+        match self.mode == Mode::Visual(kind) {
+            true => self.set_mode(Mode::Normal),
+            false => self.set_mode(Mode::Visual(kind)),
+        }
+    }
+
+    pub fn selection(&self) -> Option<Selection> {
+        match self.mode {
+            Mode::Visual(kind) => Some(Selection {
+                anchor: self.visual_anchor,
+                cursor: self.buffer.cursor,
+                kind,
+                to_end: self.visual_to_end,
+            }),
+            _ => None,
+        }
+    }
+
+    // o goes to the other end of the selection, O in Visual Block mode to the other corner on
+    // the same line
+    pub fn swap_selection_ends(&mut self, same_line: bool) {
+        self.forget_preferred_col();
+        let anchor = self.visual_anchor;
+        if same_line && self.mode == Mode::Visual(VisualKind::Block) {
+            // The corners trade display columns, which may be other columns on their lines
+            let cursor = self.buffer.cursor;
+            let text = &self.buffer.text;
+            let tab_width = self.settings.tab_width;
+            let line = |y| text.line(y).unwrap_or_default();
+            let (anchor_line, cursor_line) = (line(anchor.y), line(cursor.y));
+            let anchor_col = display_col(&anchor_line, anchor.x, tab_width);
+            let cursor_col = display_col(&cursor_line, cursor.x, tab_width);
+            self.visual_anchor.x = col_at_display(&anchor_line, cursor_col, tab_width);
+            self.buffer.cursor.x = col_at_display(&cursor_line, anchor_col, tab_width);
+        } else {
+            self.visual_anchor = self.buffer.cursor;
+            self.buffer.cursor = anchor;
+        }
+    }
+
+    // gv selects the last selection again, in Visual mode the current one becomes the last
+    fn reselect(&mut self) {
+        let Some(last) = self.buffer.last_visual else {
+            self.bell = true;
+            return;
+        };
+        self.set_mode(Mode::Normal);
+        self.set_mode(Mode::Visual(last.kind));
+        let text = &self.buffer.text;
+        let clamp = |pos: Cursor| -> Cursor {
+            let y = pos.y.min(text.len_lines() - 1);
+            (pos.x.min(text.line_len(y).saturating_sub(1)), y).into()
+        };
+        (self.visual_anchor, self.buffer.cursor) = (clamp(last.anchor), clamp(last.cursor));
+        self.visual_to_end = last.to_end;
+    }
+
+    // I and A in Visual Block mode
+    pub fn insert_in_block(&mut self, append: bool) {
+        let Some(selection) = self.selection() else {
+            return;
+        };
+        let (start, end) = selection.ordered();
+        let (left, right) = selection.columns(&self.buffer.text, self.settings.tab_width);
+        let col = match (append, selection.to_end) {
+            (true, true) => None,
+            (true, false) => Some(right + 1),
+            (false, _) => Some(left),
+        };
+        self.set_mode(Mode::Normal);
+        self.start_block_insert(start.y, end.y, col, append);
+    }
+
+    // Starts Insert mode on the first row of a block, at display column `col` or the end of the
+    // line for None
+    fn start_block_insert(&mut self, top: usize, bottom: usize, col: Option<usize>, pad: bool) {
+        self.forget_preferred_col();
+        let tab_width = self.settings.tab_width;
+        let line = self.buffer.text.line(top).unwrap_or_default().to_string();
+        let len = grapheme_count(&line);
+        let width = display_col(&line, len, tab_width);
+        let x = match col {
+            Some(col) if pad && col > width => {
+                self.buffer
+                    .insert_text((len, top).into(), &" ".repeat(col - width));
+                len + col - width
             }
-            CursorMove::WordEndForward => {
-                if let Some(line) = self.lines.get(self.cursor.y) {
-                    self.cursor.x = match word::find_word_end_forward(line, self.cursor.x) {
-                        Some(idx) => idx,
-                        None => self.cursor.x,
-                    }
+            Some(col) => col_at_display(&line, col, tab_width),
+            None => len,
+        };
+        self.block_insert = Some(BlockInsert {
+            top,
+            bottom,
+            col,
+            pad,
+            x,
+            len: self.buffer.text.line_len(top),
+        });
+        self.buffer.cursor = (x, top).into();
+        self.set_mode(Mode::Insert);
+    }
+
+    // The text typed on the first row of the block goes into the other rows, unless it took
+    // more than one line
+    fn finish_block_insert(&mut self) {
+        let Some(insert) = self.block_insert.take() else {
+            return;
+        };
+        let text = &self.buffer.text;
+        let added = text.line_len(insert.top).saturating_sub(insert.len);
+        if self.buffer.cursor.y != insert.top || added == 0 {
+            return;
+        }
+        let typed = text
+            .line(insert.top)
+            .unwrap_or_default()
+            .graphemes(true)
+            .skip(insert.x)
+            .take(added)
+            .collect::<String>();
+        let tab_width = self.settings.tab_width;
+        for y in insert.top + 1..=insert.bottom.min(text.len_lines() - 1) {
+            let line = self.buffer.text.line(y).unwrap_or_default().to_string();
+            let len = grapheme_count(&line);
+            let width = display_col(&line, len, tab_width);
+            let (x, text) = match insert.col {
+                None => (len, typed.clone()),
+                Some(col) if col <= width => (col_at_display(&line, col, tab_width), typed.clone()),
+                Some(col) if insert.pad => (len, format!("{}{typed}", " ".repeat(col - width))),
+                Some(_) => continue,
+            };
+            self.buffer.insert_text((x, y).into(), &text);
+        }
+        self.buffer.cursor = (insert.x, insert.top).into();
+    }
+
+    /// Outside of insert mode the cursor sits on a character, never past the end of the line
+    pub fn clamp_cursor(&mut self) {
+        if self.mode == Mode::Insert {
+            return;
+        }
+
+        self.buffer.cursor.x = self.buffer.cursor.x.min(
+            self.buffer
+                .text
+                .line_len(self.buffer.cursor.y)
+                .saturating_sub(1),
+        );
+    }
+
+    pub fn insert_char_at_command_cursor(&mut self, c: char) {
+        let idx = grapheme_to_byte(&self.command, self.command_x);
+        self.command.insert(idx, c);
+        self.command_x = grapheme_count(&self.command[..idx + c.len_utf8()]);
+    }
+
+    pub fn backspace_at_command_cursor(&mut self) {
+        if self.command_x == 0 {
+            if self.command.is_empty() {
+                match self.mode {
+                    Mode::Search => self.cancel_search(),
+                    _ => self.set_mode(Mode::Normal),
                 }
             }
-            CursorMove::Start => {
-                self.cursor = (0, 0).into();
-            }
-            CursorMove::End => {
-                let last_line = self.lines.len().saturating_sub(1);
-                let last_line_len = self.lines.get(last_line).map(|l| l.len()).unwrap_or(0);
-                self.cursor = (last_line_len, last_line).into();
-            }
+            return;
         }
+
+        let start = grapheme_to_byte(&self.command, self.command_x - 1);
+        let end = grapheme_to_byte(&self.command, self.command_x);
+        self.command.replace_range(start..end, "");
+        self.move_command_cursor(CursorMove::Left);
     }
 
-    pub fn move_command_cursor(&mut self, cursor_move: CursorMove) {
-        match cursor_move {
-            CursorMove::Left => {
-                self.command_x = self.command_x.saturating_sub(1);
-            }
-            CursorMove::Right => {
-                self.command_x = (self.command_x + 1).clamp(0, self.command.len());
-            }
-            CursorMove::LineBegin => {
-                self.command_x = 0;
-            }
-            CursorMove::LineEnd => {
-                self.command_x = self.command.len();
-            }
-            _ => {}
+    pub fn execute_command(&mut self) {
+        let input = self.command.clone();
+        self.set_mode(Mode::Normal);
+        if input.trim().is_empty() {
+            return;
+        }
+
+        push_history(&mut self.command_history, input.clone());
+        self.registers.last_command = input.clone();
+        self.forget_preferred_col();
+        if let Err(err) = command::parse(&input).and_then(|cmd| cmd.execute(self)) {
+            self.status_message = err.to_string();
+            self.bell = true;
         }
     }
 
-    pub fn scroll_up(&mut self, amount: usize) {
-        self.scroll.0 = self.scroll.0.saturating_sub(amount as u16);
-        self.cursor.y = self.cursor.y.saturating_sub(amount);
+    fn prompt_history(&self) -> &[String] {
+        match self.mode {
+            Mode::Search => &self.search_history,
+            _ => &self.command_history,
+        }
     }
 
-    pub fn scroll_down(&mut self, amount: usize) {
-        self.scroll.0 = (self.scroll.0 + amount as u16).clamp(0, self.lines.len() as u16 - 1);
-        self.cursor.y = (self.cursor.y + amount).clamp(0, self.lines.len() - 1);
+    pub fn command_history_prev(&mut self) {
+        if self.command_history_idx == 0 {
+            return;
+        }
+
+        self.command_history_idx -= 1;
+        self.command = self.prompt_history()[self.command_history_idx].clone();
+        self.move_command_cursor(CursorMove::LineEnd);
+    }
+
+    pub fn command_history_next(&mut self) {
+        if self.command_history_idx >= self.prompt_history().len() {
+            return;
+        }
+
+        self.command_history_idx += 1;
+        self.command = self
+            .prompt_history()
+            .get(self.command_history_idx)
+            .cloned()
+            .unwrap_or_default();
+        self.move_command_cursor(CursorMove::LineEnd);
     }
 
-    pub fn med_scroll_up(&mut self) {
-        self.scroll_up(MEDIUM_SCROLL);
+    // Closes the current window, or tab page when it holds one window, quitting the editor
+    // when it is the last one
+    pub fn quit(&mut self, force: bool) -> Result<()> {
+        if self.windows.len() > 1 {
+            return self.close_window();
+        }
+        self.quit_all(force)
     }
 
-    pub fn med_scroll_down(&mut self) {
-        self.scroll_down(MEDIUM_SCROLL);
+    // Quitting closes every buffer, so unsaved changes in any of them stop it
+    pub fn quit_all(&mut self, force: bool) -> Result<()> {
+        if !force && self.is_dirty() {
+            self.current_screen = CurrentScreen::Exiting;
+            return Ok(());
+        }
+
+        self.running = false;
+        Ok(())
     }
 
-    pub fn clear_search(&mut self) {
-        self.search.query.clear();
-        self.command_x = 0;
+    fn current_window(&self) -> &Window {
+        self.windows
+            .iter()
+            .find(|window| window.id == self.window_id)
+            .expect("current window exists")
     }
 
-    pub fn execute_current_search(&mut self) {
-        self.search.search(&self.lines);
-        if self.search.results.is_empty() {
-            self.status_message = "Pattern not found".to_string();
-            return;
+    fn current_window_mut(&mut self) -> &mut Window {
+        let id = self.window_id;
+        self.windows
+            .iter_mut()
+            .find(|window| window.id == id)
+            .expect("current window exists")
+    }
+
+    // Buffer, cursor and scroll shown in window `id`
+    pub fn window_view(&self, id: usize) -> Option<(&Buffer, Cursor, (usize, usize))> {
+        if id == self.window_id {
+            return Some((&self.buffer, self.buffer.cursor, self.buffer.scroll));
         }
-        if let Some(first_result) = self.search.results.first() {
-            self.cursor = (first_result.start, first_result.row).into();
-            if let Some((idx, _)) = self
-                .search
-                .results
+
+        let window = self.windows.iter().find(|window| window.id == id)?;
+        let buffer = self
+            .buffer_list()
+            .into_iter()
+            .find(|buffer| buffer.id == window.buffer_id)?;
+        Some((buffer, window.cursor, window.scroll))
+    }
+
+    pub fn focus_window(&mut self, id: usize) -> Result<()> {
+        if id == self.window_id {
+            return Ok(());
+        }
+
+        let target = self
+            .windows
+            .iter()
+            .find(|window| window.id == id)
+            .cloned()
+            .ok_or_else(|| anyhow!("E957: Invalid window number"))?;
+        let (cursor, scroll) = (self.buffer.cursor, self.buffer.scroll);
+        let current = self.current_window_mut();
+        current.cursor = cursor;
+        current.scroll = scroll;
+
+        self.window_id = id;
+        // Going to another window isn't a jump, even when it shows another buffer
+        if target.buffer_id != self.buffer.id {
+            let idx = self
+                .buffers
                 .iter()
-                .enumerate()
-                .find(|(_, r)| r.row == self.cursor.y)
-            {
-                self.status_message = format!(
-                    "/{} - [{}/{}]",
-                    self.search.query,
-                    idx + 1,
-                    self.search.results.len()
-                );
+                .position(|buffer| buffer.id == target.buffer_id)
+                .ok_or_else(|| anyhow!("E86: Buffer {} does not exist", target.buffer_id))?;
+            self.show_buffer(idx);
+        }
+        self.status_message.clear();
+        self.buffer.cursor = target.cursor;
+        self.buffer.scroll = target.scroll;
+        // The buffer may have been edited in another window since
+        self.buffer.cursor.y = self.buffer.cursor.y.min(self.buffer.text.len_lines() - 1);
+        self.clamp_cursor();
+        Ok(())
+    }
+
+    // Splits the current window, the new window shows `path` or the current buffer and gets
+    // the focus
+    pub fn split_window(&mut self, direction: SplitDirection, path: Option<&str>) -> Result<()> {
+        let id = self.next_window_id;
+        self.next_window_id += 1;
+        self.windows.push(Window {
+            id,
+            buffer_id: self.buffer.id,
+            cursor: self.buffer.cursor,
+            scroll: self.buffer.scroll,
+            preferred_col: self.current_window().preferred_col,
+        });
+        self.layout.split(self.window_id, id, direction);
+        self.focus_window(id)?;
+        if let Some(path) = path {
+            self.open(path)?;
+        }
+        Ok(())
+    }
+
+    // Like a split, but with a new empty buffer
+    pub fn new_window(&mut self, direction: SplitDirection) -> Result<()> {
+        self.split_window(direction, None)?;
+        let buffer = self.new_buffer();
+        self.set_buffer(buffer);
+        Ok(())
+    }
+
+    pub fn close_window(&mut self) -> Result<()> {
+        if self.layout.is_leaf(self.window_id) {
+            if self.tabs.len() > 1 {
+                return self.close_tab(None);
             }
+            return Err(anyhow!("E444: Cannot close last window"));
         }
+
+        // Focus goes to the window before it, which usually takes over its space
+        let leaves = self.layout.leaves();
+        let idx = leaves
+            .iter()
+            .position(|id| *id == self.window_id)
+            .unwrap_or(0);
+        let next = match idx {
+            0 => leaves[1],
+            _ => leaves[idx - 1],
+        };
+        let closed = self.window_id;
+        self.focus_window(next)?;
+        self.layout.remove(closed);
+        self.windows.retain(|window| window.id != closed);
+        Ok(())
     }
 
-    pub fn search_next(&mut self) {
-        if let Some((idx, _)) = self
-            .search
-            .results
+    pub fn only_window(&mut self) {
+        let closed = self.layout.leaves();
+        let id = self.window_id;
+        self.windows
+            .retain(|window| window.id == id || !closed.contains(&window.id));
+        self.layout = WindowNode::Leaf(id);
+    }
+
+    // Moves focus `offset` windows along, wrapping around
+    pub fn cycle_window(&mut self, offset: isize) -> Result<()> {
+        let leaves = self.layout.leaves();
+        let idx = leaves
             .iter()
-            .enumerate()
-            .find(|(_, r)| r.row == self.cursor.y)
-        {
-            let (idx, next) = if idx == self.search.results.len() - 1 {
-                let idx = 0;
-                (idx, &self.search.results[idx])
-            } else {
-                let idx = idx + 1;
-                (idx, &self.search.results[idx])
-            };
-            self.cursor = (next.start, next.row).into();
-            self.status_message = format!(
-                "/{} - [{}/{}]",
-                self.search.query,
-                idx + 1,
-                self.search.results.len()
-            );
+            .position(|id| *id == self.window_id)
+            .unwrap_or(0);
+        let target = (idx as isize + offset).rem_euclid(leaves.len() as isize) as usize;
+        self.focus_window(leaves[target])
+    }
+
+    pub fn move_to_window(&mut self, direction: WindowDirection) -> Result<()> {
+        let cursor = self.window_cursor_position();
+        if let Some(id) = neighbour(&self.window_areas, self.window_id, direction, cursor) {
+            self.focus_window(id)?;
         }
+        Ok(())
     }
 
-    pub fn search_prev(&mut self) {
-        if let Some((idx, _)) = self
-            .search
-            .results
+    // Screen position of the cursor in the current window, roughly
+    fn window_cursor_position(&self) -> (u16, u16) {
+        let Some((_, area)) = self
+            .window_areas
             .iter()
-            .enumerate()
-            .find(|(_, r)| r.row == self.cursor.y)
-        {
-            let (idx, prev) = if idx == 0 {
-                let idx = self.search.results.len() - 1;
-                (idx, &self.search.results[idx])
-            } else {
-                let idx = idx - 1;
-                (idx, &self.search.results[idx])
-            };
-            self.cursor = (prev.start, prev.row).into();
-            self.status_message = format!(
-                "/{} - [{}/{}]",
-                self.search.query,
-                idx + 1,
-                self.search.results.len()
-            );
-        }
+            .find(|(id, _)| *id == self.window_id)
+        else {
+            return (0, 0);
+        };
+        let row = self.buffer.cursor.y.saturating_sub(self.buffer.scroll.0) as u16;
+        let col = self
+            .cursor_display_col()
+            .saturating_sub(self.buffer.scroll.1) as u16;
+        (
+            (area.x + col).min(area.right().saturating_sub(1)),
+            (area.y + row).min(area.bottom().saturating_sub(1)),
+        )
     }
 
-    pub fn char_at(&self, coords: (usize, usize)) -> Option<char> {
-        self.lines.get(coords.1)?.chars().nth(coords.0)
+    // `size` sets the height (or width) of the current window, `delta` changes it
+    pub fn resize_window(&mut self, direction: SplitDirection, size: Option<usize>, delta: isize) {
+        let Some((_, area)) = self
+            .window_areas
+            .iter()
+            .find(|(id, _)| *id == self.window_id)
+        else {
+            return;
+        };
+        let current = match direction {
+            SplitDirection::Horizontal => area.height,
+            SplitDirection::Vertical => area.width,
+        } as isize;
+        let delta = match size {
+            Some(size) => size as isize - current,
+            None => delta,
+        };
+        let areas = self.window_areas.clone();
+        self.layout.resize(self.window_id, direction, delta, &areas);
     }
 
-    pub fn is_dirty(&self) -> Result<bool> {
-        if let Some(filename) = &self.filename {
-            let disk_file_content = fs::read_to_string(filename)?;
+    // The commands behind CTRL-W, also reachable through :wincmd. A count goes to that window for
+    // w and W, moves that many windows for h, j, k and l, and resizes by that much.
+    pub fn window_command(&mut self, key: char, count: Option<usize>) -> Result<()> {
+        let times = count.unwrap_or(1);
+        match key {
+            's' | 'S' => self.split_window(SplitDirection::Horizontal, None),
+            'v' => self.split_window(SplitDirection::Vertical, None),
+            'n' => self.new_window(SplitDirection::Horizontal),
+            'c' => self.close_window(),
+            'q' => self.quit(false),
+            'o' => {
+                self.only_window();
+                Ok(())
+            }
+            'w' | 'W' if count.is_some() => {
+                let leaves = self.layout.leaves();
+                self.focus_window(leaves[(times - 1).min(leaves.len() - 1)])
+            }
+            'w' => self.cycle_window(1),
+            'W' => self.cycle_window(-1),
+            'h' | 'j' | 'k' | 'l' => {
+                let direction = match key {
+                    'h' => WindowDirection::Left,
+                    'j' => WindowDirection::Down,
+                    'k' => WindowDirection::Up,
+                    _ => WindowDirection::Right,
+                };
+                for _ in 0..times.min(self.layout.leaves().len()) {
+                    self.move_to_window(direction)?;
+                }
+                Ok(())
+            }
+            '+' | '-' | '<' | '>' => {
+                let (direction, delta) = match key {
+                    '+' => (SplitDirection::Horizontal, 1),
+                    '-' => (SplitDirection::Horizontal, -1),
+                    '>' => (SplitDirection::Vertical, 1),
+                    _ => (SplitDirection::Vertical, -1),
+                };
+                let delta = delta * times.min(isize::MAX as usize) as isize;
+                self.resize_window(direction, None, delta);
+                Ok(())
+            }
+            '=' => {
+                self.layout.equalize();
+                Ok(())
+            }
+            _ => Ok(()),
+        }
+    }
 
-            return Ok(self.lines.join("\n") != disk_file_content);
+    pub fn goto_tab(&mut self, idx: usize) -> Result<()> {
+        if idx >= self.tabs.len() {
+            return Err(anyhow!("E475: Invalid tab page number: {}", idx + 1));
+        }
+        if idx == self.tab_idx {
+            return Ok(());
         }
 
-        Ok(false)
+        self.tabs[self.tab_idx] = TabPage {
+            layout: self.layout.clone(),
+            window_id: self.window_id,
+        };
+        let tab = self.tabs[idx].clone();
+        self.tab_idx = idx;
+        self.layout = tab.layout;
+        // The areas belong to the previous tab until the next draw
+        self.window_areas.clear();
+        self.focus_window(tab.window_id)
     }
-}
-
-struct Renderer<'a> {
-    editor: &'a mut Editor,
-}
 
-impl<'a> Renderer<'a> {
-    pub fn new(editor: &'a mut Editor) -> Self {
-        Self { editor }
+    // Moves `offset` tab pages along, wrapping around
+    pub fn cycle_tab(&mut self, offset: isize) -> Result<()> {
+        let len = self.tabs.len() as isize;
+        self.goto_tab((self.tab_idx as isize + offset).rem_euclid(len) as usize)
     }
-}
 
-impl<'a> Widget for Renderer<'a> {
-    fn render(self, area: Rect, buf: &mut Buffer)
-    where
-        Self: Sized,
-    {
-        if (self.editor.cursor.y as u16) < self.editor.scroll.0 {
-            self.editor.scroll.0 = self.editor.cursor.y as u16;
-        } else if (self.editor.cursor.y as u16) >= self.editor.scroll.0 + area.height {
-            self.editor.scroll.0 = (self.editor.cursor.y as u16) - area.height.saturating_sub(1);
+    // Opens a tab page after the current one with a single window showing `path`, or a new
+    // empty buffer
+    pub fn new_tab(&mut self, path: Option<&str>) -> Result<()> {
+        let id = self.next_window_id;
+        self.next_window_id += 1;
+        self.windows.push(Window {
+            id,
+            buffer_id: self.buffer.id,
+            cursor: self.buffer.cursor,
+            scroll: self.buffer.scroll,
+            preferred_col: self.current_window().preferred_col,
+        });
+        self.tabs.insert(
+            self.tab_idx + 1,
+            TabPage {
+                layout: WindowNode::Leaf(id),
+                window_id: id,
+            },
+        );
+        self.goto_tab(self.tab_idx + 1)?;
+        match path {
+            Some(path) => self.open(path),
+            None => {
+                let buffer = self.new_buffer();
+                self.set_buffer(buffer);
+                Ok(())
+            }
         }
+    }
 
-        // XXX: perf
-        let text_block = Paragraph::new(self.editor.lines.join("\n"))
-            .scroll(self.editor.scroll);
+    // Closes tab page `idx`, the current one by default. Its buffers stay loaded.
+    pub fn close_tab(&mut self, idx: Option<usize>) -> Result<()> {
+        let idx = idx.unwrap_or(self.tab_idx);
+        if idx >= self.tabs.len() {
+            return Err(anyhow!("E475: Invalid tab page number: {}", idx + 1));
+        }
+        if self.tabs.len() == 1 {
+            return Err(anyhow!("E784: Cannot close last tab page"));
+        }
 
-        text_block.render(area, buf);
+        if idx == self.tab_idx {
+            // Like vim, focus goes to the tab on the right unless this is the last one
+            let next = if idx + 1 < self.tabs.len() {
+                idx + 1
+            } else {
+                idx - 1
+            };
+            self.goto_tab(next)?;
+        }
+        let closed = self.tabs.remove(idx).layout.leaves();
+        self.windows.retain(|window| !closed.contains(&window.id));
+        if idx < self.tab_idx {
+            self.tab_idx -= 1;
+        }
+        Ok(())
+    }
+
+    pub fn only_tab(&mut self) -> Result<()> {
+        while self.tabs.len() > 1 {
+            let idx = if self.tab_idx == 0 { 1 } else { 0 };
+            self.close_tab(Some(idx))?;
+        }
+        Ok(())
+    }
+
+    // Moves the current tab page so it ends up at position `idx`
+    pub fn move_tab(&mut self, idx: usize) {
+        let idx = idx.min(self.tabs.len() - 1);
+        let tab = self.tabs.remove(self.tab_idx);
+        self.tabs.insert(idx, tab);
+        self.tab_idx = idx;
+    }
+
+    // Labels for the tabline: window count when there are several, then the name of the
+    // focused buffer and whether it is modified
+    pub fn tab_labels(&self) -> Vec<String> {
+        self.tabs
+            .iter()
+            .enumerate()
+            .map(|(idx, tab)| {
+                let (window_id, count) = match idx == self.tab_idx {
+                    true => (self.window_id, self.layout.leaves().len()),
+                    false => (tab.window_id, tab.layout.leaves().len()),
+                };
+                let Some((buffer, _, _)) = self.window_view(window_id) else {
+                    return String::new();
+                };
+                let name = match &buffer.filename {
+                    Some(path) => path
+                        .file_name()
+                        .map_or_else(|| buffer.name(), |name| name.to_string_lossy().to_string()),
+                    None => buffer.name(),
+                };
+                let count = match count {
+                    1 => String::new(),
+                    _ => format!("{count} "),
+                };
+                let dirty = if buffer.is_dirty() { " +" } else { "" };
+                format!(" {count}{name}{dirty} ")
+            })
+            .collect()
+    }
+
+    // Text area of window `id`, scrolled so that its cursor is visible
+    pub fn window_widget(&mut self, id: usize, area: Rect) -> Option<impl Widget + '_> {
+        let active = id == self.window_id;
+        let selection = match active {
+            true => self.selection(),
+            false => None,
+        };
+        let (buffer, cursor, scroll) = match active {
+            true => {
+                let cursor = self.buffer.cursor;
+                (&mut self.buffer, cursor, None)
+            }
+            false => {
+                let window = self.windows.iter_mut().find(|window| window.id == id)?;
+                let buffer = match window.buffer_id == self.buffer.id {
+                    true => &mut self.buffer,
+                    false => self
+                        .buffers
+                        .iter_mut()
+                        .find(|buffer| buffer.id == window.buffer_id)?,
+                };
+                let mut cursor = window.cursor;
+                cursor.y = cursor.y.min(buffer.text.len_lines() - 1);
+                (buffer, cursor, Some(&mut window.scroll))
+            }
+        };
+
+        let tab_width = self.settings.tab_width;
+        let scroll = match scroll {
+            Some(scroll) => {
+                *scroll = scroll_to_cursor(buffer, cursor, *scroll, area, tab_width);
+                *scroll
+            }
+            None => {
+                buffer.scroll = scroll_to_cursor(buffer, cursor, buffer.scroll, area, tab_width);
+                buffer.scroll
+            }
+        };
+
+        let confirm = match active {
+            true => self
+                .substitute_state
+                .as_ref()
+                .and_then(|state| state.current.as_ref())
+                .map(|current| current.result),
+            false => None,
+        };
+        Some(Renderer {
+            buffer,
+            cursor,
+            scroll,
+            tab_width,
+            theme: &self.theme,
+            selection,
+            confirm,
+            active,
+        })
+    }
+
+    pub fn commit_history(&mut self) {
+        self.buffer.history.commit();
+    }
+
+    pub fn undo(&mut self) {
+        match self.buffer.history.undo() {
+            Some((edits, cursor)) => {
+                self.apply_history_step(&edits, cursor);
+                self.status_message = format!("before #{}", self.buffer.history.current() + 1);
+            }
+            None => self.status_message = "Already at oldest change".to_string(),
+        }
+    }
+
+    pub fn redo(&mut self) {
+        match self.buffer.history.redo() {
+            Some((edits, cursor)) => {
+                self.apply_history_step(&edits, cursor);
+                self.status_message = format!("after #{}", self.buffer.history.current());
+            }
+            None => self.status_message = "Already at newest change".to_string(),
+        }
+    }
+
+    // Jumps to the revision `offset` steps away in creation order, across undo branches
+    pub fn undo_chronological(&mut self, offset: isize) {
+        let target = self
+            .buffer
+            .history
+            .current()
+            .saturating_add_signed(offset)
+            .min(self.buffer.history.len() - 1);
+        self.undo_goto(target);
+    }
+
+    pub fn undo_goto(&mut self, target: usize) {
+        if let Some((edits, cursor)) = self.buffer.history.goto(target) {
+            self.apply_history_step(&edits, cursor);
+        }
+        self.status_message = format!("at #{}", self.buffer.history.current());
+    }
+
+    fn apply_history_step(&mut self, edits: &[Edit], cursor: Cursor) {
+        for edit in edits {
+            self.buffer.apply_edit(edit);
+        }
+        self.buffer.cursor.y = cursor.y.min(self.buffer.text.len_lines() - 1);
+        self.buffer.cursor.x = cursor
+            .x
+            .min(self.buffer.text.line_len(self.buffer.cursor.y));
+        self.clamp_cursor();
+    }
+
+    pub fn insert_char_at_cursor(&mut self, c: char) {
+        self.forget_preferred_col();
+        self.buffer.cursor = self.buffer.insert_text(self.buffer.cursor, &c.to_string());
+        self.inserted.push(c);
+    }
+
+    // Text pasted into the terminal goes in as it is, without indenting, and is a single undo
+    // step even in Insert mode
+    pub fn paste_text(&mut self, text: &str) {
+        let text = text.replace("\r\n", "\n").replace('\r', "\n");
+        match self.mode {
+            Mode::Command | Mode::Search => {
+                for c in text.chars() {
+                    self.insert_char_at_command_cursor(if c == '\n' { ' ' } else { c });
+                }
+            }
+            Mode::Insert => {
+                self.forget_preferred_col();
+                self.commit_history();
+                self.buffer.cursor = self.buffer.insert_text(self.buffer.cursor, &text);
+                self.inserted.push_str(&text);
+                self.commit_history();
+            }
+            // Like inserting the text before the cursor and pressing Esc
+            Mode::Normal | Mode::Visual(_) => {
+                self.set_mode(Mode::Normal);
+                self.pending_keys.clear();
+                self.forget_preferred_col();
+                let end = self.buffer.insert_text(self.buffer.cursor, &text);
+                self.buffer.cursor = (end.x.saturating_sub(1), end.y).into();
+                self.clamp_cursor();
+                self.commit_history();
+            }
+        }
+    }
+
+    pub fn backspace_at_cursor(&mut self) {
+        if self.buffer.cursor == (0, 0).into() {
+            return;
+        }
+        self.forget_preferred_col();
+        self.inserted.pop();
+        if self.buffer.cursor.x == 0 {
+            let join_idx = self.buffer.text.line_len(self.buffer.cursor.y - 1);
+            self.buffer.delete_text(
+                (join_idx, self.buffer.cursor.y - 1).into(),
+                self.buffer.cursor,
+            );
+            self.buffer.cursor = (join_idx, self.buffer.cursor.y - 1).into();
+            return;
+        }
+
+        self.buffer.delete_text(
+            (self.buffer.cursor.x - 1, self.buffer.cursor.y).into(),
+            self.buffer.cursor,
+        );
+        self.buffer.cursor.x -= 1;
+    }
+
+    pub fn newline_above_cursor(&mut self) {
+        self.buffer
+            .insert_text((0, self.buffer.cursor.y).into(), "\n");
+        self.move_cursor(CursorMove::LineBegin);
+    }
+
+    pub fn newline_under_cursor(&mut self) {
+        self.forget_preferred_col();
+        let line_len = self.buffer.text.line_len(self.buffer.cursor.y);
+        self.buffer
+            .insert_text((line_len, self.buffer.cursor.y).into(), "\n");
+        self.buffer.cursor = (0, self.buffer.cursor.y + 1).into();
+    }
+
+    pub fn newline_at_cursor(&mut self) {
+        self.forget_preferred_col();
+        self.buffer.insert_text(self.buffer.cursor, "\n");
+        self.inserted.push('\n');
+        self.buffer.cursor = (0, self.buffer.cursor.y + 1).into();
+    }
+
+    // What a tab press inserts, and what > and < shift lines by
+    fn indent_unit(&self) -> String {
+        match self.settings.tab_type {
+            TabType::Spaces(n) => " ".repeat(n),
+            TabType::Tabs(n) => "\t".repeat(n),
+        }
+    }
+
+    pub fn insert_tab(&mut self) {
+        self.forget_preferred_col();
+        let tab = self.indent_unit();
+        self.buffer.cursor = self.buffer.insert_text(self.buffer.cursor, &tab);
+        self.inserted.push_str(&tab);
+    }
+
+    pub fn move_cursor(&mut self, cursor_move: CursorMove) {
+        if !matches!(cursor_move, CursorMove::Up | CursorMove::Down) {
+            self.forget_preferred_col();
+        }
+        match cursor_move {
+            CursorMove::Up => {
+                if let Some(y) = self.buffer.cursor.y.checked_sub(1) {
+                    self.move_to_line(y);
+                }
+            }
+            CursorMove::Down => {
+                let y = self.buffer.cursor.y + 1;
+                if y < self.buffer.text.len_lines() {
+                    self.move_to_line(y);
+                }
+            }
+            CursorMove::Left => {
+                self.buffer.cursor.x = self.buffer.cursor.x.saturating_sub(1);
+            }
+            CursorMove::Right => {
+                if self.buffer.cursor.x < self.buffer.text.line_len(self.buffer.cursor.y) {
+                    self.buffer.cursor.x += 1;
+                }
+            }
+            CursorMove::LineBegin => {
+                self.buffer.cursor.x = 0;
+            }
+            // j and k stay at the end of the lines after $
+            CursorMove::LineEnd => {
+                self.buffer.cursor.x = self.buffer.text.line_len(self.buffer.cursor.y);
+                self.current_window_mut().preferred_col = Some(usize::MAX);
+            }
+            CursorMove::WordStartForward
+            | CursorMove::WordStartBackward
+            | CursorMove::WordEndForward
+            | CursorMove::WordEndBackward
+            | CursorMove::BigWordStartForward
+            | CursorMove::BigWordStartBackward
+            | CursorMove::BigWordEndForward
+            | CursorMove::BigWordEndBackward => {
+                let find = match cursor_move {
+                    CursorMove::WordStartForward | CursorMove::BigWordStartForward => {
+                        word::next_word_start
+                    }
+                    CursorMove::WordStartBackward | CursorMove::BigWordStartBackward => {
+                        word::prev_word_start
+                    }
+                    CursorMove::WordEndForward | CursorMove::BigWordEndForward => {
+                        word::next_word_end
+                    }
+                    _ => word::prev_word_end,
+                };
+                let big = matches!(
+                    cursor_move,
+                    CursorMove::BigWordStartForward
+                        | CursorMove::BigWordStartBackward
+                        | CursorMove::BigWordEndForward
+                        | CursorMove::BigWordEndBackward
+                );
+                self.buffer.cursor =
+                    find(&self.buffer.text, self.buffer.cursor, self.keyword(), big);
+            }
+            CursorMove::Start => {
+                self.buffer.cursor = (0, 0).into();
+            }
+            CursorMove::End => {
+                let last_line = self.buffer.text.len_lines().saturating_sub(1);
+                let last_line_len = self.buffer.text.line_len(last_line);
+                self.buffer.cursor = (last_line_len, last_line).into();
+            }
+            // Only marks in this buffer, jump_to_mark goes to the others
+            CursorMove::Mark(name) | CursorMove::MarkLine(name) => {
+                if let Some(mark) = self.mark(name) {
+                    let y = mark.y.min(self.buffer.text.len_lines() - 1);
+                    self.buffer.cursor = match cursor_move {
+                        CursorMove::Mark(_) => (mark.x.min(self.buffer.text.line_len(y)), y),
+                        _ => (self.buffer.first_non_blank(y), y),
+                    }
+                    .into();
+                }
+            }
+            CursorMove::FindForward(_)
+            | CursorMove::FindBackward(_)
+            | CursorMove::TillForward(_)
+            | CursorMove::TillBackward(_)
+            | CursorMove::RepeatFind
+            | CursorMove::RepeatFindReverse => {
+                if let Some((to, _)) = self.find_target(cursor_move, None) {
+                    self.buffer.cursor = to;
+                }
+            }
+        }
+    }
+
+    // Where the find `motion` goes with `count`, along with the f, F, t or T that ; and , stand
+    // for. None when the character isn't on the line often enough.
+    fn find_target(
+        &mut self,
+        motion: CursorMove,
+        count: Option<usize>,
+    ) -> Option<(Cursor, CursorMove)> {
+        let repeat = matches!(
+            motion,
+            CursorMove::RepeatFind | CursorMove::RepeatFindReverse
+        );
+        let motion = match motion {
+            CursorMove::RepeatFind => self.last_find?,
+            CursorMove::RepeatFindReverse => self.last_find?.reversed(),
+            motion => {
+                self.last_find = Some(motion);
+                motion
+            }
+        };
+        let cursor = self.buffer.cursor;
+        let line = self.buffer.text.line(cursor.y)?;
+        let x = find_char(&line, cursor.x, motion, count.unwrap_or(1), repeat)?;
+        Some(((x, cursor.y).into(), motion))
+    }
+
+    // Characters words are made of in the current buffer, its language can replace the setting
+    fn keyword(&self) -> &IsKeyword {
+        self.buffer
+            .highlighter
+            .as_ref()
+            .and_then(|highlighter| highlighter.language.iskeyword.as_ref())
+            .unwrap_or(&self.settings.iskeyword)
+    }
+
+    // The display column j, k and scrolling aim for
+    fn preferred_col(&self) -> usize {
+        self.current_window()
+            .preferred_col
+            .unwrap_or_else(|| self.cursor_display_col())
+    }
+
+    // Edits and motions other than j, k and scrolling make the cursor's own column the one to
+    // aim for
+    fn forget_preferred_col(&mut self) {
+        self.current_window_mut().preferred_col = None;
+    }
+
+    // Moves the cursor to line `y`, at the preferred display column or as near to it as the line
+    // allows. Tabs and wide characters make that a different grapheme from line to line.
+    fn move_to_line(&mut self, y: usize) {
+        let col = self.preferred_col();
+        let line = self.buffer.text.line(y).unwrap_or_default();
+        self.buffer.cursor = (col_at_display(&line, col, self.settings.tab_width), y).into();
+        self.current_window_mut().preferred_col = Some(col);
+    }
+
+    pub fn run_normal_command(&mut self, command: NormalCommand) -> Result<()> {
+        // Only j, k and scrolling keep the column they aim for. Going to other windows and tabs,
+        // and commands that just lead to others, such as v, / and @, leave it alone too.
+        if !matches!(
+            command.action,
+            Action::Motion(CursorMove::Up | CursorMove::Down)
+                | Action::Scroll { .. }
+                | Action::Window(_)
+                | Action::TabNext
+                | Action::TabPrev
+                | Action::Repeat
+                | Action::Record(_)
+                | Action::Play(_)
+                | Action::SetMark(_)
+                | Action::Visual(_)
+                | Action::Search { .. }
+                | Action::CommandLine
+        ) {
+            self.forget_preferred_col();
+        }
+        match command.action {
+            Action::Motion(CursorMove::Mark(name)) => self.jump_to_mark(name, false),
+            Action::Motion(CursorMove::MarkLine(name)) => self.jump_to_mark(name, true),
+            Action::Motion(motion) => {
+                let target = self.motion_target(motion, command.count);
+                if target == self.buffer.cursor && motion.can_fail() {
+                    self.bell = true;
+                }
+                if let CursorMove::Start | CursorMove::End = motion {
+                    self.push_jump();
+                }
+                // Up and down keep a block reaching the ends of the lines
+                match motion {
+                    CursorMove::LineEnd => self.visual_to_end = true,
+                    CursorMove::Up | CursorMove::Down => {}
+                    _ => self.visual_to_end = false,
+                }
+                self.buffer.cursor = target;
+                Ok(())
+            }
+            Action::Operator(operator, target) => {
+                self.apply_operator(operator, target, command.count, command.register)
+            }
+            Action::Put { before } => {
+                self.put(command.register, before, command.count.unwrap_or(1))
+            }
+            Action::Select(object) => {
+                let count = command.count.unwrap_or(1);
+                let (text, cursor) = (&self.buffer.text, self.buffer.cursor);
+                if let Some(range) =
+                    text_object::select(text, cursor, object, count, self.keyword())
+                {
+                    self.select_range(range);
+                }
+                Ok(())
+            }
+            // With a count gt goes to that tab page, gT goes back that many
+            Action::TabNext => match command.count {
+                Some(count) => self.goto_tab(count.saturating_sub(1)),
+                None => self.cycle_tab(1),
+            },
+            Action::TabPrev => self.cycle_tab(-signed(command.count.unwrap_or(1))),
+            // Replaying needs the key handling, see repeat_change in main.rs
+            Action::Repeat => Ok(()),
+            Action::Record(name) => {
+                self.macros.recording = Some((name, vec![]));
+                Ok(())
+            }
+            Action::Play(name) => self.play_macro(name, command.count.unwrap_or(1)),
+            Action::SetMark(name) => {
+                self.set_mark(name);
+                Ok(())
+            }
+            Action::Reselect => {
+                self.reselect();
+                Ok(())
+            }
+            Action::Insert(at) => {
+                self.start_insert(at, command.count.unwrap_or(1));
+                Ok(())
+            }
+            Action::Visual(kind) => {
+                self.toggle_visual(kind);
+                Ok(())
+            }
+            Action::Search { backward } => {
+                let direction = match backward {
+                    true => SearchDirection::Backward,
+                    false => SearchDirection::Forward,
+                };
+                self.start_search(direction, command.count.unwrap_or(1));
+                Ok(())
+            }
+            Action::SearchNext { reverse } => {
+                let direction = match reverse {
+                    true => self.buffer.search.direction.reverse(),
+                    false => self.buffer.search.direction,
+                };
+                self.jump_to_match(direction, command.count.unwrap_or(1));
+                Ok(())
+            }
+            Action::Undo => {
+                let revisions = self.buffer.history.len();
+                for _ in 0..command.count.unwrap_or(1).min(revisions) {
+                    self.undo();
+                }
+                Ok(())
+            }
+            Action::Redo => {
+                let revisions = self.buffer.history.len();
+                for _ in 0..command.count.unwrap_or(1).min(revisions) {
+                    self.redo();
+                }
+                Ok(())
+            }
+            Action::Scroll { down } => {
+                self.scroll_amount = command.count.or(self.scroll_amount);
+                let amount = self.scroll_amount.unwrap_or(MEDIUM_SCROLL);
+                match down {
+                    true => self.scroll_down(amount),
+                    false => self.scroll_up(amount),
+                }
+                Ok(())
+            }
+            Action::JumpOlder => self.jump_history(-signed(command.count.unwrap_or(1))),
+            Action::JumpNewer => self.jump_history(signed(command.count.unwrap_or(1))),
+            Action::Window(key) => self.window_command(key, command.count),
+            // 3: starts the command line with .,.+2
+            Action::CommandLine => {
+                self.set_mode(Mode::Command);
+                if let Some(count) = command.count.filter(|count| *count > 1) {
+                    self.command = format!(".,.+{}", count - 1);
+                    self.command_x = self.command.len();
+                }
+                Ok(())
+            }
+        }
+    }
+
+    // The recorded keys go into the register as text, without making it the unnamed register
+    pub fn stop_recording(&mut self) -> Result<()> {
+        let Some((name, keys)) = self.macros.recording.take() else {
+            return Ok(());
+        };
+        self.registers.record(name, macros::keys_to_text(&keys))
+    }
+
+    // Queues the keys in register `name` `count` times, ahead of the rest of any macro being
+    // played. @@ plays the last one again and @: repeats the last command line.
+    fn play_macro(&mut self, name: char, count: usize) -> Result<()> {
+        let name = match name {
+            '@' => self
+                .macros
+                .last
+                .ok_or_else(|| anyhow!("E748: No previously used register"))?,
+            name => name,
+        };
+        let text = match name {
+            ':' => format!(":{}\n", self.registers.last_command),
+            name => self.register(name)?.text,
+        };
+        self.macros.last = Some(name);
+
+        self.macros.plays = self.macros.plays.saturating_add(count);
+        if self.macros.plays > macros::MAX_PLAYS {
+            self.macros.queue.clear();
+            return Err(anyhow!("E169: Command too recursive"));
+        }
+        let keys = macros::text_to_keys(&text);
+        for _ in 0..count {
+            for key in keys.iter().rev() {
+                self.macros.queue.push_front(*key);
+            }
+        }
+        Ok(())
+    }
+
+    // Where the cursor ends up after `motion`, without moving it. A count repeats the motion,
+    // except for gg and G where it is the line to go to.
+    fn motion_target(&mut self, motion: CursorMove, count: Option<usize>) -> Cursor {
+        let cursor = self.buffer.cursor;
+        match (motion, count) {
+            (CursorMove::Start | CursorMove::End, Some(line)) => {
+                let y = line.saturating_sub(1).min(self.buffer.text.len_lines() - 1);
+                self.buffer.cursor = (self.buffer.first_non_blank(y), y).into();
+            }
+            (motion, count) if motion.is_find() => match self.find_target(motion, count) {
+                Some((to, _)) => self.buffer.cursor = to,
+                None => self.bell = true,
+            },
+            // Past the number of characters a count only leaves the cursor stuck at an end
+            _ => {
+                let count = count.unwrap_or(1).min(self.buffer.text.len_chars() + 1);
+                for _ in 0..count {
+                    self.move_cursor(motion);
+                }
+            }
+        }
+        std::mem::replace(&mut self.buffer.cursor, cursor)
+    }
+
+    fn operator_range(
+        &mut self,
+        operator: Operator,
+        target: Target,
+        count: Option<usize>,
+    ) -> Option<OperatorRange> {
+        let cursor = self.buffer.cursor;
+        let motion = match target {
+            Target::Lines => {
+                let last = cursor.y.saturating_add(count.unwrap_or(1) - 1);
+                let last = last.min(self.buffer.text.len_lines() - 1);
+                return Some(OperatorRange::Lines(cursor.y, last));
+            }
+            Target::Object(object) => {
+                let (text, count) = (&self.buffer.text, count.unwrap_or(1));
+                return text_object::select(text, cursor, object, count, self.keyword());
+            }
+            Target::Selection => {
+                let selection = self.selection()?;
+                return Some(selection.range(&self.buffer.text, self.settings.tab_width));
+            }
+            Target::SelectionLines => {
+                let (start, end) = self.selection()?.ordered();
+                return Some(OperatorRange::Lines(start.y, end.y));
+            }
+            Target::Motion(motion) => motion,
+        };
+        if let CursorMove::Mark(name) | CursorMove::MarkLine(name) = motion {
+            self.mark(name)?;
+        }
+
+        // cw works like ce, and cW like cE, leaving the whitespace after the word alone. On the
+        // last character of a word that character is the first word changed.
+        let on_blank = self
+            .buffer
+            .char_at(cursor.into())
+            .is_none_or(char::is_whitespace);
+        let mut count = count;
+        let motion = match (operator, motion) {
+            (Operator::Change, CursorMove::WordStartForward | CursorMove::BigWordStartForward)
+                if !on_blank =>
+            {
+                let big = motion == CursorMove::BigWordStartForward;
+                let line = self.buffer.text.line(cursor.y).unwrap_or_default();
+                if word::is_word_end(&line, cursor.x, self.keyword(), big) {
+                    match count.unwrap_or(1) {
+                        1 => {
+                            let end = (cursor.x + 1, cursor.y).into();
+                            return Some(OperatorRange::Chars(cursor, end));
+                        }
+                        n => count = Some(n - 1),
+                    }
+                }
+                match big {
+                    true => CursorMove::BigWordEndForward,
+                    false => CursorMove::WordEndForward,
+                }
+            }
+            _ => motion,
+        };
+
+        // dj and dk fail unless there are count lines to go, instead of taking the current line
+        let lines = count.unwrap_or(1);
+        match motion {
+            CursorMove::Down if cursor.y.saturating_add(lines) >= self.buffer.text.len_lines() => {
+                return None
+            }
+            CursorMove::Up if cursor.y < lines => return None,
+            _ => {}
+        }
+        let (mut to, motion) = match motion.is_find() {
+            true => self.find_target(motion, count)?,
+            false => (self.motion_target(motion, count), motion),
+        };
+        // dw on the last word of a line stops at the end of the line, instead of taking the line
+        // break and the indent of the next line along
+        if let CursorMove::WordStartForward | CursorMove::BigWordStartForward = motion {
+            if to == cursor {
+                to = (self.buffer.text.line_len(cursor.y), cursor.y).into();
+            } else if to.y > cursor.y && to.x <= self.buffer.first_non_blank(to.y) {
+                to = (self.buffer.text.line_len(to.y - 1), to.y - 1).into();
+            }
+        }
+
+        let (start, mut end) = match (cursor.y, cursor.x) <= (to.y, to.x) {
+            true => (cursor, to),
+            false => (to, cursor),
+        };
+        let range = match motion.kind() {
+            MotionKind::Linewise => OperatorRange::Lines(start.y, end.y),
+            MotionKind::Inclusive => {
+                end.x = (end.x + 1).min(self.buffer.text.line_len(end.y));
+                OperatorRange::Chars(start, end)
+            }
+            MotionKind::Exclusive => OperatorRange::Chars(start, end),
+        };
+        Some(range)
+    }
+
+    pub fn apply_operator(
+        &mut self,
+        operator: Operator,
+        target: Target,
+        count: Option<usize>,
+        register: Option<char>,
+    ) -> Result<()> {
+        let Some(range) = self.operator_range(operator, target, count) else {
+            self.bell = true;
+            return Ok(());
+        };
+        if let Target::Selection | Target::SelectionLines = target {
+            self.set_mode(Mode::Normal);
+        }
+        match operator {
+            Operator::Delete => {
+                self.registers.delete(register, self.range_text(range))?;
+                self.delete_range(range);
+            }
+            // The text typed in place of a block goes into each of its rows
+            Operator::Change => {
+                self.registers.delete(register, self.range_text(range))?;
+                match range {
+                    OperatorRange::Block(start, end) => {
+                        self.delete_range(range);
+                        self.start_block_insert(start.y, end.y, Some(start.x), false);
+                    }
+                    _ => self.change_range(range),
+                }
+            }
+            Operator::Yank => {
+                self.registers.yank(register, self.range_text(range))?;
+                self.yank_range(range);
+            }
+            Operator::Indent | Operator::Outdent => {
+                let (first, last) = match range {
+                    OperatorRange::Chars(start, end) | OperatorRange::Block(start, end) => {
+                        (start.y, end.y)
+                    }
+                    OperatorRange::Lines(first, last) => (first, last),
+                };
+                self.shift_lines(first, last, operator == Operator::Indent);
+            }
+            Operator::Lowercase | Operator::Uppercase | Operator::ToggleCase => {
+                self.change_case(range, operator)
+            }
+        }
+        Ok(())
+    }
+
+    // Visual mode selections include the character under the cursor. Selecting lines, e.g.
+    // with ip, switches to Visual Line mode.
+    fn select_range(&mut self, range: OperatorRange) {
+        if let OperatorRange::Lines(..) = range {
+            self.set_mode(Mode::Visual(VisualKind::Line));
+        }
+        let (start, end) = self.range_bounds(range);
+        let text = &self.buffer.text;
+        let last = text
+            .pos_to_char(end)
+            .saturating_sub(1)
+            .max(text.pos_to_char(start));
+        self.visual_anchor = start;
+        self.buffer.cursor = text.char_to_pos(last);
+    }
+
+    // Start and end of the text in `range`, a line range stops before its last newline. For a
+    // block these are its corners, see block_rows for the text in it.
+    fn range_bounds(&self, range: OperatorRange) -> (Cursor, Cursor) {
+        match range {
+            OperatorRange::Chars(start, end) | OperatorRange::Block(start, end) => (start, end),
+            OperatorRange::Lines(first, last) => (
+                (0, first).into(),
+                (self.buffer.text.line_len(last), last).into(),
+            ),
+        }
+    }
+
+    // Where each line of a block starts and ends, short lines having less of it
+    fn block_rows(&self, start: Cursor, end: Cursor) -> Vec<(Cursor, Cursor)> {
+        (start.y..=end.y)
+            .map(|y| {
+                let line = self.buffer.text.line(y).unwrap_or_default();
+                let (left, right) = block_span(&line, start.x, end.x, self.settings.tab_width);
+                ((left, y).into(), (right, y).into())
+            })
+            .collect()
+    }
+
+    // The cursor goes to the top left of a block after an operator
+    fn block_start(&self, start: Cursor) -> Cursor {
+        let line = self.buffer.text.line(start.y).unwrap_or_default();
+        let x = col_at_display(&line, start.x, self.settings.tab_width);
+        (x, start.y).into()
+    }
+
+    fn range_text(&self, range: OperatorRange) -> Register {
+        if let OperatorRange::Block(start, end) = range {
+            let rows = self
+                .block_rows(start, end)
+                .into_iter()
+                .map(|(start, end)| self.buffer.text_between(start, end))
+                .collect::<Vec<_>>();
+            return Register::new(rows.join("\n"), RegisterKind::Blockwise);
+        }
+        let (start, end) = self.range_bounds(range);
+        let mut text = self.buffer.text_between(start, end);
+        let kind = match range {
+            OperatorRange::Lines(..) => {
+                text.push('\n');
+                RegisterKind::Linewise
+            }
+            _ => RegisterKind::Charwise,
+        };
+        Register::new(text, kind)
+    }
+
+    // Vim only mentions changes to more than two lines
+    fn report_lines(&mut self, count: usize, message: &str) {
+        if count > 2 {
+            self.status_message = format!("{count} {message}");
+        }
+    }
+
+    fn delete_range(&mut self, range: OperatorRange) {
+        match range {
+            OperatorRange::Chars(start, end) => {
+                self.buffer.delete_text(start, end);
+                self.buffer.cursor = start;
+            }
+            OperatorRange::Lines(first, last) => {
+                self.delete_lines(first, last);
+                self.report_lines(last - first + 1, "fewer lines");
+            }
+            OperatorRange::Block(start, end) => {
+                let cursor = self.block_start(start);
+                for (row_start, row_end) in self.block_rows(start, end) {
+                    self.buffer.delete_text(row_start, row_end);
+                }
+                self.buffer.cursor = cursor;
+            }
+        }
+    }
+
+    fn delete_lines(&mut self, first: usize, last: usize) {
+        let text = &self.buffer.text;
+        let (start, end) = if last + 1 < text.len_lines() {
+            ((0, first), (0, last + 1))
+        } else if first > 0 {
+            // The last line has no newline of its own, take the one before it
+            (
+                (text.line_len(first - 1), first - 1),
+                (text.line_len(last), last),
+            )
+        } else {
+            ((0, 0), (text.line_len(last), last))
+        };
+        self.buffer.delete_text(start.into(), end.into());
+        let y = first.min(self.buffer.text.len_lines() - 1);
+        self.buffer.cursor = (self.buffer.first_non_blank(y), y).into();
+    }
+
+    // Line ranges are emptied but kept as one line to type into
+    fn change_range(&mut self, range: OperatorRange) {
+        let (start, end) = self.range_bounds(range);
+        self.buffer.delete_text(start, end);
+        self.buffer.cursor = start;
+        self.set_mode(Mode::Insert);
+    }
+
+    fn yank_range(&mut self, range: OperatorRange) {
+        match range {
+            OperatorRange::Chars(start, _) => self.buffer.cursor = start,
+            OperatorRange::Block(start, _) => self.buffer.cursor = self.block_start(start),
+            OperatorRange::Lines(first, last) => {
+                self.buffer.cursor.y = first;
+                self.report_lines(last - first + 1, "lines yanked");
+            }
+        }
+    }
+
+    // :yank, which leaves the cursor where it is
+    pub fn yank_lines(&mut self, first: usize, last: usize, register: Option<char>) -> Result<()> {
+        let range = OperatorRange::Lines(first, last);
+        self.registers.yank(register, self.range_text(range))?;
+        self.report_lines(last - first + 1, "lines yanked");
+        Ok(())
+    }
+
+    // Contents of register `name`, including the read-only ones and the clipboard
+    pub fn register(&self, name: char) -> Result<Register> {
+        let text = match name {
+            '+' | '*' => return self.registers.clipboard.get(),
+            '.' => self.registers.last_insert.clone(),
+            ':' => self.registers.last_command.clone(),
+            // The black hole register reads as empty
+            '_' => String::new(),
+            '%' => self
+                .buffer
+                .filename
+                .as_ref()
+                .map(|path| path.display().to_string())
+                .unwrap_or_default(),
+            _ => {
+                return self
+                    .registers
+                    .get(name)
+                    .cloned()
+                    .ok_or_else(|| anyhow!("E354: Invalid register name: '{}'", name))
+            }
+        };
+        Ok(Register::new(text, RegisterKind::Charwise))
+    }
+
+    // Non-empty registers in the order :registers lists them, limited to `names` if given
+    pub fn register_list(&self, names: Option<&str>) -> Vec<(char, Register)> {
+        let order = "\"0123456789abcdefghijklmnopqrstuvwxyz-.:%+*";
+        order
+            .chars()
+            .filter(|name| names.is_none_or(|names| names.contains(*name)))
+            .filter_map(|name| Some((name, self.register(name).ok()?)))
+            .filter(|(_, register)| !register.is_empty())
+            .collect()
+    }
+
+    // Pastes register `name` `count` times after the cursor, or before it for P
+    pub fn put(&mut self, name: Option<char>, before: bool, count: usize) -> Result<()> {
+        let name = name.unwrap_or('"');
+        let register = self.register(name)?;
+        if register.is_empty() {
+            // "_p quietly puts nothing
+            return match name {
+                '_' => Ok(()),
+                _ => Err(anyhow!("E353: Nothing in register {}", name)),
+            };
+        }
+        let count = repeat_count(&register.text, count);
+        let cursor = self.buffer.cursor;
+        // On an empty line there is nothing to paste after
+        let x = match before || self.buffer.text.line_len(cursor.y) == 0 {
+            true => cursor.x,
+            false => cursor.x + 1,
+        };
+
+        match register.kind {
+            RegisterKind::Charwise => {
+                let text = register.text.repeat(count);
+                let start = (x, cursor.y).into();
+                let end = self.buffer.insert_text(start, &text);
+                // Multi-line text leaves the cursor at its start, like vim
+                self.buffer.cursor = match text.contains('\n') {
+                    true => start,
+                    false => (end.x.saturating_sub(1), end.y).into(),
+                };
+            }
+            RegisterKind::Linewise => {
+                let text = register.text.repeat(count);
+                let y = if before { cursor.y } else { cursor.y + 1 };
+                if y < self.buffer.text.len_lines() {
+                    self.buffer.insert_text((0, y).into(), &text);
+                } else {
+                    // The last line has no newline of its own to paste after
+                    let text = format!("\n{}", text.strip_suffix('\n').unwrap_or(&text));
+                    let end = (self.buffer.text.line_len(y - 1), y - 1).into();
+                    self.buffer.insert_text(end, &text);
+                }
+                self.buffer.cursor = (self.buffer.first_non_blank(y), y).into();
+            }
+            // Rows shorter than the block are padded to keep the text after them lined up
+            RegisterKind::Blockwise => {
+                let width = register.text.lines().map(grapheme_count).max().unwrap_or(0);
+                for (i, row) in register.text.lines().enumerate() {
+                    let y = cursor.y + i;
+                    if y >= self.buffer.text.len_lines() {
+                        let last = self.buffer.text.len_lines() - 1;
+                        let end = (self.buffer.text.line_len(last), last).into();
+                        self.buffer.insert_text(end, "\n");
+                    }
+                    let len = self.buffer.text.line_len(y);
+                    let padding = " ".repeat(x.saturating_sub(len));
+                    let row = match x < len {
+                        true => format!("{row}{}", " ".repeat(width - grapheme_count(row))),
+                        false => row.to_string(),
+                    };
+                    let text = format!("{padding}{}", row.repeat(count));
+                    self.buffer.insert_text((x.min(len), y).into(), &text);
+                }
+                self.buffer.cursor = (x, cursor.y).into();
+            }
+        }
+        Ok(())
+    }
+
+    pub fn show_registers(&mut self, names: Option<&str>) {
+        self.current_screen = CurrentScreen::Registers(names.map(str::to_string));
+    }
+
+    fn shift_lines(&mut self, first: usize, last: usize, indent: bool) {
+        let unit = self.indent_unit();
+        let width = match self.settings.tab_type {
+            TabType::Spaces(n) => n,
+            TabType::Tabs(n) => n * self.settings.tab_width,
+        };
+        for y in first..=last {
+            let line = self.buffer.text.line(y).unwrap_or_default().to_string();
+            if indent {
+                // Like vim, empty lines are left without trailing whitespace
+                if !line.is_empty() {
+                    self.buffer.insert_text((0, y).into(), &unit);
+                }
+                continue;
+            }
+
+            let mut col = 0;
+            let mut remove = 0;
+            for c in line.chars() {
+                if col >= width {
+                    break;
+                }
+                match c {
+                    ' ' => col += 1,
+                    '\t' => col += self.settings.tab_width,
+                    _ => break,
+                }
+                remove += 1;
+            }
+            self.buffer.delete_text((0, y).into(), (remove, y).into());
+        }
+        self.buffer.cursor = (self.buffer.first_non_blank(first), first).into();
+        let direction = if indent { ">" } else { "<" };
+        self.report_lines(last - first + 1, &format!("lines {direction}ed 1 time"));
+    }
+
+    fn change_case(&mut self, range: OperatorRange, operator: Operator) {
+        let parts = match range {
+            OperatorRange::Block(start, end) => self.block_rows(start, end),
+            _ => vec![self.range_bounds(range)],
+        };
+        for (start, end) in parts {
+            let text = self.buffer.text_between(start, end);
+            let changed = text
+                .chars()
+                .map(|c| match operator {
+                    Operator::Lowercase => c.to_lowercase().to_string(),
+                    Operator::Uppercase => c.to_uppercase().to_string(),
+                    _ if c.is_lowercase() => c.to_uppercase().to_string(),
+                    _ => c.to_lowercase().to_string(),
+                })
+                .collect::<String>();
+            if changed != text {
+                self.buffer.delete_text(start, end);
+                self.buffer.insert_text(start, &changed);
+            }
+        }
+        match range {
+            OperatorRange::Chars(start, _) => self.buffer.cursor = start,
+            OperatorRange::Block(start, _) => self.buffer.cursor = self.block_start(start),
+            OperatorRange::Lines(first, last) => {
+                self.buffer.cursor.y = first;
+                self.report_lines(last - first + 1, "lines changed");
+            }
+        }
+    }
+
+    pub fn move_command_cursor(&mut self, cursor_move: CursorMove) {
+        match cursor_move {
+            CursorMove::Left => {
+                self.command_x = self.command_x.saturating_sub(1);
+            }
+            CursorMove::Right => {
+                self.command_x = (self.command_x + 1).clamp(0, grapheme_count(&self.command));
+            }
+            CursorMove::LineBegin => {
+                self.command_x = 0;
+            }
+            CursorMove::LineEnd => {
+                self.command_x = grapheme_count(&self.command);
+            }
+            _ => {}
+        }
+    }
+
+    pub fn scroll_up(&mut self, amount: usize) {
+        self.buffer.scroll.0 = self.buffer.scroll.0.saturating_sub(amount);
+        self.move_to_line(self.buffer.cursor.y.saturating_sub(amount));
+    }
+
+    pub fn scroll_down(&mut self, amount: usize) {
+        let last_line = self.buffer.text.len_lines() - 1;
+        self.buffer.scroll.0 = (self.buffer.scroll.0.saturating_add(amount)).clamp(0, last_line);
+        self.move_to_line((self.buffer.cursor.y.saturating_add(amount)).clamp(0, last_line));
+    }
+
+    pub fn start_search(&mut self, direction: SearchDirection, count: usize) {
+        self.search_origin = Some((self.buffer.cursor, self.buffer.search.query.clone()));
+        self.search_count = count;
+        self.buffer.search.direction = direction;
+        self.set_mode(Mode::Search);
+    }
+
+    // Moves the cursor to the first match of what has been typed so far
+    pub fn incremental_search(&mut self) {
+        let Some((origin, _)) = self.search_origin.clone() else {
+            return;
+        };
+
+        self.buffer.cursor = origin;
+        if self.command.is_empty() || self.buffer.search.set_query(&self.command.clone()).is_err() {
+            self.buffer.search.highlight = false;
+            return;
+        }
+
+        self.buffer.search.highlight = true;
+        if let Some((found, _)) =
+            self.buffer
+                .search
+                .find_from(&self.buffer.text, origin, self.buffer.search.direction)
+        {
+            self.buffer.cursor = (found.start, found.row).into();
+        }
+    }
+
+    pub fn cancel_search(&mut self) {
+        if let Some((origin, query)) = self.search_origin.take() {
+            self.buffer.cursor = origin;
+            self.buffer.search.highlight =
+                self.buffer.search.set_query(&query).is_ok() && !query.is_empty();
+        }
+        self.set_mode(Mode::Normal);
+    }
+
+    pub fn execute_current_search(&mut self) {
+        let input = self.command.clone();
+        let Some((origin, previous)) = self.search_origin.take() else {
+            return;
+        };
+        self.set_mode(Mode::Normal);
+        self.buffer.cursor = origin;
+
+        // An empty pattern repeats the last search
+        let query = if input.is_empty() { previous } else { input };
+        if query.is_empty() {
+            self.status_message = "E35: No previous regular expression".to_string();
+            self.bell = true;
+            return;
+        }
+        push_history(&mut self.search_history, query.clone());
+        if let Err(err) = self.buffer.search.set_query(&query) {
+            self.status_message = err.to_string();
+            self.bell = true;
+            return;
+        }
+
+        self.jump_to_match(self.buffer.search.direction, self.search_count);
+    }
+
+    // Goes to the `count`th match from the cursor
+    fn jump_to_match(&mut self, direction: SearchDirection, count: usize) {
+        if self.buffer.search.query.is_empty() {
+            self.status_message = "E35: No previous regular expression".to_string();
+            self.bell = true;
+            return;
+        }
+
+        // Going round all the matches comes back to the same one, so a count needn't go round
+        // more than once
+        self.buffer
+            .search
+            .search(&self.buffer.text, self.buffer.history.current());
+        let total = self.buffer.search.results.len().max(1);
+        let mut from = self.buffer.cursor;
+        let mut found = None;
+        let mut wrapped = count > total;
+        for _ in 0..(count.max(1) - 1) % total + 1 {
+            let Some((next, wrap)) =
+                self.buffer
+                    .search
+                    .find_from(&self.buffer.text, from, direction)
+            else {
+                break;
+            };
+            from = (next.start, next.row).into();
+            wrapped |= wrap;
+            found = Some(next);
+        }
+        let Some(found) = found else {
+            self.status_message = format!("E486: Pattern not found: {}", self.buffer.search.query);
+            self.bell = true;
+            return;
+        };
+
+        self.push_jump();
+        self.forget_preferred_col();
+        self.buffer.cursor = (found.start, found.row).into();
+        self.clamp_cursor();
+        self.buffer.search.highlight = true;
+        let idx = self
+            .buffer
+            .search
+            .results
+            .iter()
+            .position(|r| *r == found)
+            .unwrap_or(0);
+        self.status_message = format!(
+            "{}{} - [{}/{}]",
+            direction.prompt(),
+            self.buffer.search.query,
+            idx + 1,
+            self.buffer.search.results.len()
+        );
+        if wrapped {
+            self.status_message.push_str(match direction {
+                SearchDirection::Forward => " search hit BOTTOM, continuing at TOP",
+                SearchDirection::Backward => " search hit TOP, continuing at BOTTOM",
+            });
+        }
+    }
+
+    // Mark `name` when it is in the current buffer
+    pub fn mark(&self, name: char) -> Option<Cursor> {
+        match name {
+            '<' => self
+                .buffer
+                .last_visual
+                .map(|selection| selection.ordered().0),
+            '>' => self
+                .buffer
+                .last_visual
+                .map(|selection| selection.ordered().1),
+            '`' => self.buffer.marks.get(&'\'').copied(),
+            name => self.buffer.marks.get(&name).copied(),
+        }
+    }
+
+    pub fn mark_line(&self, mark: char) -> Option<usize> {
+        self.mark(mark).map(|cursor| cursor.y)
+    }
+
+    // m{mark}. A global mark moves here from whichever file it was in before, and m' records the
+    // position as a jump.
+    fn set_mark(&mut self, name: char) {
+        if name == '\'' || name == '`' {
+            self.push_jump();
+            return;
+        }
+        if mark::is_global(name) {
+            for buffer in &mut self.buffers {
+                buffer.marks.remove(&name);
+            }
+            self.file_marks.remove(&name);
+        }
+        self.buffer.marks.insert(name, self.buffer.cursor);
+    }
+
+    fn current_jump(&self) -> Jump {
+        Jump {
+            buffer_id: self.buffer.id,
+            cursor: self.buffer.cursor,
+        }
+    }
+
+    // Called before a jump, so CTRL-O and '' can go back to where it started
+    pub fn push_jump(&mut self) {
+        self.buffer.marks.insert('\'', self.buffer.cursor);
+        self.jumps.push(self.current_jump());
+    }
+
+    // `{mark} and '{mark}, the latter going to the first non-blank of the line. A global mark can
+    // take the current window to another buffer, or open the file it was set in.
+    fn jump_to_mark(&mut self, name: char, linewise: bool) -> Result<()> {
+        let motion = match linewise {
+            true => CursorMove::MarkLine(name),
+            false => CursorMove::Mark(name),
+        };
+        if self.mark(name).is_none() {
+            if !mark::is_global(name) || self.mode != Mode::Normal {
+                return Err(anyhow!("E20: Mark not set"));
+            }
+            self.show_global_mark(name)?;
+            self.move_cursor(motion);
+            return Ok(());
+        }
+        // Worked out before the jump replaces the ' mark
+        let target = self.motion_target(motion, None);
+        self.push_jump();
+        self.buffer.cursor = target;
+        Ok(())
+    }
+
+    // Switches to the buffer holding global mark `name`, or opens its file
+    fn show_global_mark(&mut self, name: char) -> Result<()> {
+        let id = self
+            .buffers
+            .iter()
+            .find(|buffer| buffer.marks.contains_key(&name))
+            .map(|buffer| buffer.id);
+        match (id, self.file_marks.get(&name)) {
+            (Some(id), _) => self.switch_buffer(id),
+            (None, Some((path, _))) => self.open(&path.display().to_string()),
+            (None, None) => Err(anyhow!("E20: Mark not set")),
+        }
+    }
+
+    // CTRL-O and CTRL-I, going `offset` entries back or forward in the jumplist
+    pub fn jump_history(&mut self, offset: isize) -> Result<()> {
+        let Some(jump) = self.jumps.go(self.current_jump(), offset) else {
+            self.bell = true;
+            return Ok(());
+        };
+        if jump.buffer_id != self.buffer.id {
+            let idx = self
+                .buffers
+                .iter()
+                .position(|buffer| buffer.id == jump.buffer_id)
+                .ok_or_else(|| anyhow!("E86: Buffer {} does not exist", jump.buffer_id))?;
+            self.show_buffer(idx);
+            self.status_message = self.buffer_info();
+        }
+        self.buffer.cursor = jump.cursor;
+        self.clamp_cursor();
+        Ok(())
+    }
+
+    // Marks as :marks lists them, limited to `names` if given. The file is given for global
+    // marks in other buffers, the others are in the current one.
+    pub fn mark_list(&self, names: Option<&str>) -> Vec<(char, Cursor, Option<String>)> {
+        let order = "'abcdefghijklmnopqrstuvwxyzABCDEFGHIJKLMNOPQRSTUVWXYZ<>";
+        order
+            .chars()
+            .filter(|name| names.is_none_or(|names| names.contains(*name)))
+            .filter_map(|name| {
+                if let Some(cursor) = self.mark(name) {
+                    return Some((name, cursor, None));
+                }
+                if let Some(buffer) = self.buffers.iter().find(|b| b.marks.contains_key(&name)) {
+                    return Some((name, buffer.marks[&name], Some(buffer.name())));
+                }
+                let (path, cursor) = self.file_marks.get(&name)?;
+                Some((name, *cursor, Some(path.display().to_string())))
+            })
+            .collect()
+    }
+
+    pub fn show_marks(&mut self, names: Option<&str>) {
+        self.current_screen = CurrentScreen::Marks(names.map(str::to_string));
+    }
+
+    pub fn substitute(&mut self, arg: &str, range: (usize, usize)) -> Result<()> {
+        // A bare :s repeats the last substitution
+        let substitute = match arg.is_empty() {
+            true => self
+                .last_substitute
+                .clone()
+                .ok_or_else(|| anyhow!("E35: No previous regular expression"))?,
+            false => substitute::parse(arg)?,
+        };
+        let pattern = match substitute.pattern.is_empty() {
+            true => self.buffer.search.query.clone(),
+            false => substitute.pattern.clone(),
+        };
+        if pattern.is_empty() {
+            return Err(anyhow!("E35: No previous regular expression"));
+        }
+
+        let mut state = SubstituteState::new(&substitute, &pattern, range)?;
+        self.last_substitute = Some(substitute);
+        if self.buffer.search.set_query(&pattern).is_ok() {
+            self.buffer.search.highlight = true;
+        }
+        if state.find_next(&self.buffer.text).is_none() {
+            return Err(anyhow!("E486: Pattern not found: {}", pattern));
+        }
+
+        if state.confirm {
+            self.show_substitute_match(&state);
+            self.substitute_state = Some(state);
+            self.current_screen = CurrentScreen::Substitute;
+            return Ok(());
+        }
+
+        while state.current.is_some() {
+            self.replace_substitute_match(&mut state);
+            state.find_next(&self.buffer.text);
+        }
+        self.finish_substitute(state);
+        Ok(())
+    }
+
+    // Handles the y/n/a/q/l answers to the prompt of :s with the c flag
+    pub fn answer_substitute(&mut self, answer: char) {
+        let Some(mut state) = self.substitute_state.take() else {
+            return;
+        };
+
+        match answer {
+            'y' => {
+                self.replace_substitute_match(&mut state);
+                state.find_next(&self.buffer.text);
+            }
+            'n' => {
+                state.skipped();
+                state.find_next(&self.buffer.text);
+            }
+            'a' => {
+                while state.current.is_some() {
+                    self.replace_substitute_match(&mut state);
+                    state.find_next(&self.buffer.text);
+                }
+            }
+            'l' => {
+                self.replace_substitute_match(&mut state);
+            }
+            'q' => {
+                state.current = None;
+            }
+            _ => {
+                self.substitute_state = Some(state);
+                return;
+            }
+        }
+
+        if state.current.is_some() {
+            self.show_substitute_match(&state);
+            self.substitute_state = Some(state);
+        } else {
+            self.finish_substitute(state);
+        }
+    }
+
+    fn show_substitute_match(&mut self, state: &SubstituteState) {
+        if let Some(current) = &state.current {
+            self.buffer.cursor = (current.result.start, current.result.row).into();
+            self.status_message = format!(
+                "replace with {} (y/n/a/q/l)?",
+                current.replacement.replace('\n', "^M")
+            );
+        }
+    }
+
+    fn replace_substitute_match(&mut self, state: &mut SubstituteState) {
+        let Some(current) = &state.current else {
+            return;
+        };
+
+        let result = current.result;
+        let replacement = current.replacement.clone();
+        self.buffer.delete_text(
+            (result.start, result.row).into(),
+            (result.end, result.row).into(),
+        );
+        self.buffer
+            .insert_text((result.start, result.row).into(), &replacement);
+        state.replaced();
+    }
+
+    fn finish_substitute(&mut self, state: SubstituteState) {
+        self.current_screen = CurrentScreen::Editing;
+        if let Some(line) = state.last_line {
+            self.buffer.cursor = (0, line).into();
+        }
+        self.clamp_cursor();
+
+        let plural = |n: usize| if n == 1 { "" } else { "s" };
+        self.status_message = format!(
+            "{} substitution{} on {} line{}",
+            state.count,
+            plural(state.count),
+            state.lines,
+            plural(state.lines)
+        );
+    }
+
+    pub fn cursor_display_col(&self) -> usize {
+        let line = self
+            .buffer
+            .text
+            .line(self.buffer.cursor.y)
+            .unwrap_or_default();
+        display_col(&line, self.buffer.cursor.x, self.settings.tab_width)
+    }
+}
+
+// Column f, F, t or T goes to from `x`, at the `count`th `c` on the line. When ; repeats a t or
+// T that is already next to its character it goes on to the next one.
+fn find_char(
+    line: &str,
+    x: usize,
+    motion: CursorMove,
+    count: usize,
+    repeat: bool,
+) -> Option<usize> {
+    let (c, forward, till) = match motion {
+        CursorMove::FindForward(c) => (c, true, false),
+        CursorMove::FindBackward(c) => (c, false, false),
+        CursorMove::TillForward(c) => (c, true, true),
+        CursorMove::TillBackward(c) => (c, false, true),
+        _ => return None,
+    };
+    let graphemes = line.graphemes(true).collect::<Vec<_>>();
+    let is_match = |col: &usize| graphemes[*col].starts_with(c);
+    let skip = usize::from(till && repeat && count == 1);
+    let count = count.max(1);
+    match forward {
+        true => {
+            let col = (x + 1 + skip..graphemes.len())
+                .filter(is_match)
+                .nth(count - 1)?;
+            Some(if till { col - 1 } else { col })
+        }
+        false => {
+            let col = (0..x.saturating_sub(skip))
+                .rev()
+                .filter(is_match)
+                .nth(count - 1)?;
+            Some(if till { col + 1 } else { col })
+        }
+    }
+}
+
+// Adds `entry` to the command or search history, moving it to the end if it was already there
+fn push_history(history: &mut Vec<String>, entry: String) {
+    history.retain(|old| *old != entry);
+    history.push(entry);
+    if history.len() > HISTORY_MAX {
+        history.remove(0);
+    }
+}
+
+// How many times `text` can go in for a count, at least once and within MAX_REPEAT_LEN
+fn repeat_count(text: &str, count: usize) -> usize {
+    count.min(MAX_REPEAT_LEN / text.len().max(1)).max(1)
+}
+
+// A count as an offset to go back or forward by
+fn signed(count: usize) -> isize {
+    count.min(isize::MAX as usize) as isize
+}
+
+// Scroll offsets that keep `cursor` inside an `area` sized view
+fn scroll_to_cursor(
+    buffer: &Buffer,
+    cursor: Cursor,
+    scroll: (usize, usize),
+    area: Rect,
+    tab_width: usize,
+) -> (usize, usize) {
+    let (height, width) = (area.height as usize, area.width as usize);
+    let (mut top, mut left) = scroll;
+    if cursor.y < top {
+        top = cursor.y;
+    } else if cursor.y >= top + height {
+        top = cursor.y - height.saturating_sub(1);
+    }
+
+    let line = buffer.text.line(cursor.y).unwrap_or_default();
+    let cursor_col = display_col(&line, cursor.x, tab_width);
+    if cursor_col < left {
+        left = cursor_col;
+    } else if cursor_col >= left + width {
+        left = cursor_col - width.saturating_sub(1);
+    }
+    (top, left)
+}
+
+struct Renderer<'a> {
+    buffer: &'a mut Buffer,
+    cursor: Cursor,
+    scroll: (usize, usize),
+    tab_width: usize,
+    theme: &'a Theme,
+    // Visual mode selection and the :s///c match, only in the current window
+    selection: Option<Selection>,
+    confirm: Option<SearchResult>,
+    active: bool,
+}
+
+impl<'a> Widget for Renderer<'a> {
+    fn render(self, area: Rect, buf: &mut ratatui::buffer::Buffer)
+    where
+        Self: Sized,
+    {
+        let height = area.height as usize;
+        let width = area.width as usize;
+        let tab_width = self.tab_width;
+        let (top, left) = self.scroll;
+        let theme = self.theme;
+        buf.set_style(area, theme.text);
+        let (selection, confirm) = (self.selection, self.confirm);
+        let buffer = self.buffer;
+        // Styles of the language's scopes, looked up once instead of per grapheme
+        let scope_styles = match &buffer.highlighter {
+            Some(highlighter) => highlighter
+                .language
+                .scopes
+                .iter()
+                .map(|scope| theme.scope(scope))
+                .collect(),
+            None => vec![],
+        };
+        // Only the visible lines are ever pulled out of the rope
+        for (row, line) in (top..top + height)
+            .map_while(|y| buffer.text.line(y))
+            .enumerate()
+        {
+            let y = area.y + row as u16;
+            if self.active && top + row == self.cursor.y {
+                buf.set_style(Rect::new(area.x, y, area.width, 1), theme.cursor_line);
+            }
+            let matches = match buffer.search.highlight {
+                true => buffer.search.line_matches(&line, top + row),
+                false => vec![],
+            };
+            let spans = match &mut buffer.highlighter {
+                Some(highlighter) => highlighter.line_spans(&buffer.text, top + row).to_vec(),
+                None => vec![],
+            };
+            let selected = selection.and_then(|s| s.row_span(&buffer.text, top + row, tab_width));
+            let mut span_idx = 0;
+            let mut col = 0;
+            for (i, (byte, g)) in line.grapheme_indices(true).enumerate() {
+                while spans.get(span_idx).is_some_and(|span| span.end <= byte) {
+                    span_idx += 1;
+                }
+                let g_width = grapheme_width(g, col, tab_width);
+                if col >= left + width {
+                    break;
+                }
+                if col >= left {
+                    let x = area.x + (col - left) as u16;
+                    let symbol = if g == "\t" {
+                        " ".repeat(g_width)
+                    } else {
+                        g.to_string()
+                    };
+                    let mut style = match spans.get(span_idx) {
+                        Some(span) if span.start <= byte => scope_styles[span.scope],
+                        _ => Style::default(),
+                    };
+                    if selected.is_some_and(|(start, end)| (start..end).contains(&i)) {
+                        style = style.patch(theme.selection);
+                    }
+                    let in_match =
+                        |m: &SearchResult| m.row == top + row && (m.start..m.end).contains(&i);
+                    if confirm.as_ref().is_some_and(in_match) {
+                        style = style.patch(theme.current_match);
+                    } else if matches.iter().any(in_match) {
+                        style = style.patch(theme.search_match);
+                    }
+                    buf.set_stringn(x, y, symbol, (area.right() - x) as usize, style);
+                }
+                col += g_width;
+            }
+        }
     }
 }
@@ -0,0 +1,271 @@
+use crate::cursor::Cursor;
+
+#[derive(Clone, Debug)]
+pub enum Edit {
+    Insert { at: usize, text: String },
+    Delete { at: usize, text: String },
+}
+
+impl Edit {
+    pub fn inverse(&self) -> Edit {
+        match self {
+            Edit::Insert { at, text } => Edit::Delete {
+                at: *at,
+                text: text.clone(),
+            },
+            Edit::Delete { at, text } => Edit::Insert {
+                at: *at,
+                text: text.clone(),
+            },
+        }
+    }
+}
+
+struct Revision {
+    parent: usize,
+    // Child that redo follows, the most recently visited branch
+    last_child: Option<usize>,
+    edits: Vec<Edit>,
+    cursor: Cursor,
+}
+
+// Undo tree. Revisions are numbered in the order they were created, revision 0 being the
+// unmodified buffer, so undoing and then editing starts a new branch instead of discarding
+// the old one.
+pub struct History {
+    revisions: Vec<Revision>,
+    current: usize,
+    pending: Vec<Edit>,
+    pending_cursor: Cursor,
+}
+
+impl Default for History {
+    fn default() -> Self {
+        Self {
+            revisions: vec![Revision {
+                parent: 0,
+                last_child: None,
+                edits: vec![],
+                cursor: Cursor::default(),
+            }],
+            current: 0,
+            pending: vec![],
+            pending_cursor: Cursor::default(),
+        }
+    }
+}
+
+impl History {
+    pub fn current(&self) -> usize {
+        self.current
+    }
+
+    pub fn len(&self) -> usize {
+        self.revisions.len()
+    }
+
+    // `cursor` is where the cursor was before the edit, restored when the change is undone
+    pub fn record(&mut self, edit: Edit, cursor: Cursor) {
+        if self.pending.is_empty() {
+            self.pending_cursor = cursor;
+        }
+        self.pending.push(edit);
+    }
+
+    // Closes the current group of edits into a single undo step
+    pub fn commit(&mut self) {
+        if self.pending.is_empty() {
+            return;
+        }
+
+        let idx = self.revisions.len();
+        self.revisions.push(Revision {
+            parent: self.current,
+            last_child: None,
+            edits: std::mem::take(&mut self.pending),
+            cursor: self.pending_cursor,
+        });
+        self.revisions[self.current].last_child = Some(idx);
+        self.current = idx;
+    }
+
+    pub fn undo(&mut self) -> Option<(Vec<Edit>, Cursor)> {
+        self.commit();
+        if self.current == 0 {
+            return None;
+        }
+
+        let rev = &self.revisions[self.current];
+        let edits = rev.edits.iter().rev().map(Edit::inverse).collect();
+        let cursor = rev.cursor;
+        let parent = rev.parent;
+        self.revisions[parent].last_child = Some(self.current);
+        self.current = parent;
+        Some((edits, cursor))
+    }
+
+    pub fn redo(&mut self) -> Option<(Vec<Edit>, Cursor)> {
+        self.commit();
+        let child = self.revisions[self.current].last_child?;
+        self.current = child;
+        let rev = &self.revisions[child];
+        Some((rev.edits.clone(), rev.cursor))
+    }
+
+    // Moves to any revision in the tree by undoing up to the common ancestor and redoing down
+    // the target's branch
+    pub fn goto(&mut self, target: usize) -> Option<(Vec<Edit>, Cursor)> {
+        self.commit();
+        if target >= self.revisions.len() || target == self.current {
+            return None;
+        }
+
+        let mut ancestors = vec![self.current];
+        while let Some(&rev) = ancestors.last() {
+            if rev == 0 {
+                break;
+            }
+            ancestors.push(self.revisions[rev].parent);
+        }
+
+        let mut down = vec![];
+        let mut rev = target;
+        while !ancestors.contains(&rev) {
+            down.push(rev);
+            rev = self.revisions[rev].parent;
+        }
+        let common = rev;
+
+        let mut edits = vec![];
+        let mut cursor = self.revisions[self.current].cursor;
+        while self.current != common {
+            let (undone, undo_cursor) = self.undo()?;
+            edits.extend(undone);
+            cursor = undo_cursor;
+        }
+        for rev in down.into_iter().rev() {
+            let parent = self.revisions[rev].parent;
+            self.revisions[parent].last_child = Some(rev);
+            let (redone, redo_cursor) = self.redo()?;
+            edits.extend(redone);
+            cursor = redo_cursor;
+        }
+        Some((edits, cursor))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Applies the steps History hands back to a plain string, like Buffer::apply_edit
+    fn apply(text: &mut String, step: Option<(Vec<Edit>, Cursor)>) {
+        for edit in step.expect("a step to apply").0 {
+            match edit {
+                Edit::Insert { at, text: s } => text.insert_str(at, &s),
+                Edit::Delete { at, text: s } => text.replace_range(at..at + s.len(), ""),
+            }
+        }
+    }
+
+    fn insert(history: &mut History, text: &mut String, at: usize, s: &str) {
+        text.insert_str(at, s);
+        history.record(
+            Edit::Insert {
+                at,
+                text: s.to_string(),
+            },
+            Cursor::default(),
+        );
+        history.commit();
+    }
+
+    #[test]
+    fn undo_and_redo_walk_one_branch() {
+        let (mut history, mut text) = (History::default(), String::new());
+        insert(&mut history, &mut text, 0, "a");
+        insert(&mut history, &mut text, 1, "b");
+        assert_eq!(history.current(), 2);
+
+        apply(&mut text, history.undo());
+        assert_eq!(text, "a");
+        apply(&mut text, history.undo());
+        assert_eq!(text, "");
+        assert!(history.undo().is_none());
+
+        apply(&mut text, history.redo());
+        apply(&mut text, history.redo());
+        assert_eq!(text, "ab");
+        assert!(history.redo().is_none());
+    }
+
+    #[test]
+    fn edits_recorded_together_are_one_step() {
+        let (mut history, mut text) = (History::default(), String::from("xy"));
+        history.record(
+            Edit::Delete {
+                at: 0,
+                text: "x".to_string(),
+            },
+            (1, 0).into(),
+        );
+        history.record(
+            Edit::Insert {
+                at: 0,
+                text: "z".to_string(),
+            },
+            Cursor::default(),
+        );
+        text = text.replacen('x', "z", 1);
+        let step = history.undo();
+        assert_eq!(
+            step.as_ref().map(|(_, cursor)| *cursor),
+            Some((1, 0).into())
+        );
+        apply(&mut text, step);
+        assert_eq!(text, "xy");
+        assert_eq!(history.len(), 2);
+    }
+
+    #[test]
+    fn editing_after_undo_keeps_the_old_branch() {
+        let (mut history, mut text) = (History::default(), String::new());
+        insert(&mut history, &mut text, 0, "a");
+        insert(&mut history, &mut text, 1, "b");
+        apply(&mut text, history.undo());
+        insert(&mut history, &mut text, 1, "c");
+        assert_eq!((text.as_str(), history.current()), ("ac", 3));
+
+        // Redo follows the newest branch
+        apply(&mut text, history.undo());
+        apply(&mut text, history.redo());
+        assert_eq!(text, "ac");
+
+        // Revision 2 is on the other branch and still reachable
+        apply(&mut text, history.goto(2));
+        assert_eq!((text.as_str(), history.current()), ("ab", 2));
+        assert!(history.redo().is_none());
+        apply(&mut text, history.undo());
+        apply(&mut text, history.redo());
+        assert_eq!(text, "ab");
+    }
+
+    // :earlier and :later step through revisions in the order they were made, across branches
+    #[test]
+    fn goto_moves_in_creation_order() {
+        let (mut history, mut text) = (History::default(), String::new());
+        insert(&mut history, &mut text, 0, "a");
+        insert(&mut history, &mut text, 1, "b");
+        apply(&mut text, history.undo());
+        apply(&mut text, history.undo());
+        insert(&mut history, &mut text, 0, "x");
+
+        let texts = ["", "a", "ab", "x"];
+        for target in [2, 1, 0, 3, 2, 3] {
+            apply(&mut text, history.goto(target));
+            assert_eq!((text.as_str(), history.current()), (texts[target], target));
+        }
+        assert!(history.goto(3).is_none());
+        assert!(history.goto(4).is_none());
+    }
+}
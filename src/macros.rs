@@ -0,0 +1,162 @@
+use std::collections::VecDeque;
+
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+
+// Macros played from one typed key, past which they are taken to be endlessly recursive
+pub const MAX_PLAYS: usize = 10_000;
+
+// Keys recorded with q and played with @. A macro is kept in its register as text, with the
+// special keys written like <Esc> or <C-w>, so it can be edited and put back into the register.
+#[derive(Default)]
+pub struct Macros {
+    // Register being recorded into and the keys typed so far
+    pub recording: Option<(char, Vec<KeyEvent>)>,
+    // Register played last, for @@
+    pub last: Option<char>,
+    // Keys of the macros being played that haven't run yet
+    pub queue: VecDeque<KeyEvent>,
+    // Macros started since the last typed key
+    pub plays: usize,
+}
+
+const KEY_NAMES: &[(&str, KeyCode)] = &[
+    ("Esc", KeyCode::Esc),
+    ("CR", KeyCode::Enter),
+    ("BS", KeyCode::Backspace),
+    ("Tab", KeyCode::Tab),
+    ("Del", KeyCode::Delete),
+    ("Up", KeyCode::Up),
+    ("Down", KeyCode::Down),
+    ("Left", KeyCode::Left),
+    ("Right", KeyCode::Right),
+    ("Home", KeyCode::Home),
+    ("End", KeyCode::End),
+    ("PageUp", KeyCode::PageUp),
+    ("PageDown", KeyCode::PageDown),
+    ("lt", KeyCode::Char('<')),
+];
+
+fn key_name(code: KeyCode) -> Option<&'static str> {
+    KEY_NAMES
+        .iter()
+        .find(|(_, key)| *key == code)
+        .map(|(name, _)| *name)
+}
+
+pub fn keys_to_text(keys: &[KeyEvent]) -> String {
+    let mut text = String::new();
+    for key in keys {
+        let ctrl = key.modifiers.contains(KeyModifiers::CONTROL);
+        match (key.code, ctrl) {
+            (KeyCode::Char(c), true) => text.push_str(&format!("<C-{}>", c)),
+            (KeyCode::Char(c), false) if c != '<' => text.push(c),
+            (code, ctrl) => {
+                // Keys without a name can't be played back and are left out
+                let Some(name) = key_name(code) else {
+                    continue;
+                };
+                match ctrl {
+                    true => text.push_str(&format!("<C-{}>", name)),
+                    false => text.push_str(&format!("<{}>", name)),
+                }
+            }
+        }
+    }
+    text
+}
+
+// A `<` that doesn't start a key name is taken as it is, and a line break is <CR>
+pub fn text_to_keys(text: &str) -> Vec<KeyEvent> {
+    let mut keys = vec![];
+    let mut rest = text;
+    while let Some(c) = rest.chars().next() {
+        if let Some((key, len)) = parse_key_name(rest) {
+            keys.push(key);
+            rest = &rest[len..];
+            continue;
+        }
+        let key = match c {
+            '\n' => KeyEvent::new(KeyCode::Enter, KeyModifiers::NONE),
+            c if c.is_uppercase() => KeyEvent::new(KeyCode::Char(c), KeyModifiers::SHIFT),
+            c => KeyEvent::new(KeyCode::Char(c), KeyModifiers::NONE),
+        };
+        keys.push(key);
+        rest = &rest[c.len_utf8()..];
+    }
+    keys
+}
+
+// The key written at the start of `text` as `<Name>` or `<C-x>`, and the length of it
+fn parse_key_name(text: &str) -> Option<(KeyEvent, usize)> {
+    let rest = text.strip_prefix('<')?;
+    let written = &rest[..rest.find('>')?];
+    let (name, modifiers) = match written
+        .strip_prefix("C-")
+        .or_else(|| written.strip_prefix("c-"))
+    {
+        Some(name) => (name, KeyModifiers::CONTROL),
+        None => (written, KeyModifiers::NONE),
+    };
+    let mut chars = name.chars();
+    let code = match (chars.next(), chars.next()) {
+        (Some(c), None) if modifiers == KeyModifiers::CONTROL => KeyCode::Char(c),
+        _ => KEY_NAMES
+            .iter()
+            .find(|(key_name, _)| key_name.eq_ignore_ascii_case(name))
+            .map(|(_, code)| *code)?,
+    };
+    Some((KeyEvent::new(code, modifiers), written.len() + 2))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(code: KeyCode) -> KeyEvent {
+        KeyEvent::new(code, KeyModifiers::NONE)
+    }
+
+    fn ctrl(c: char) -> KeyEvent {
+        KeyEvent::new(KeyCode::Char(c), KeyModifiers::CONTROL)
+    }
+
+    #[test]
+    fn keys_round_trip() {
+        let keys = vec![
+            key(KeyCode::Char('i')),
+            key(KeyCode::Char('<')),
+            KeyEvent::new(KeyCode::Char('X'), KeyModifiers::SHIFT),
+            key(KeyCode::Esc),
+            ctrl('x'),
+            ctrl('<'),
+            key(KeyCode::Enter),
+            key(KeyCode::Tab),
+            KeyEvent::new(KeyCode::Up, KeyModifiers::CONTROL),
+            key(KeyCode::Char('é')),
+        ];
+        let text = keys_to_text(&keys);
+        assert_eq!(text, "i<lt>X<Esc><C-x><C-<><CR><Tab><C-Up>é");
+        assert_eq!(text_to_keys(&text), keys);
+    }
+
+    #[test]
+    fn text_to_keys_forms() {
+        assert_eq!(
+            text_to_keys("<esc><c-w>"),
+            vec![key(KeyCode::Esc), ctrl('w')]
+        );
+        assert_eq!(text_to_keys("a\nb")[1], key(KeyCode::Enter));
+        // A < that doesn't start a key name is typed as it is
+        let keys = text_to_keys("a<b <Nope>");
+        assert_eq!(keys.len(), 10);
+        assert_eq!(keys[1], key(KeyCode::Char('<')));
+        assert_eq!(text_to_keys("<"), vec![key(KeyCode::Char('<'))]);
+        assert_eq!(text_to_keys("<C-").len(), 3);
+    }
+
+    #[test]
+    fn unnamed_keys_are_left_out() {
+        let keys = [key(KeyCode::F(5)), key(KeyCode::Char('x'))];
+        assert_eq!(keys_to_text(&keys), "x");
+    }
+}
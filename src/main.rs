@@ -1,20 +1,24 @@
 use std::{
-    io::{self, stdout},
+    io::{self, stdout, Write},
     panic::{set_hook, take_hook},
 };
 
 use anyhow::{Context, Result};
 use cli::Cli;
-use config::Settings;
+use clipboard::Clipboard;
+use config::{Settings, Theme};
 use crossterm::{
     event::{
-        self, DisableMouseCapture, EnableMouseCapture, Event, KeyCode, KeyEvent, KeyModifiers,
+        self, DisableBracketedPaste, DisableMouseCapture, EnableBracketedPaste,
+        EnableMouseCapture, Event, KeyCode, KeyEvent, KeyModifiers,
     },
     execute,
     terminal::{disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen},
     ExecutableCommand,
 };
-use editor::{CurrentScreen, CursorMove, Editor};
+use editor::{CurrentScreen, CursorMove, Editor, Mode};
+use normal::{Action, Parse};
+use selection::VisualKind;
 use figment::{
     providers::{Format, Toml},
     Figment,
@@ -22,15 +26,32 @@ use figment::{
 use ratatui::prelude::*;
 use clap::Parser;
 use ui::ui;
-use util::pe_config_file_path;
+use syntax::LanguageRegistry;
+use util::{pe_config_file_path, pe_syntax_dir};
 
+mod buffer;
 mod cli;
+mod clipboard;
+mod command;
 mod config;
 mod cursor;
 mod editor;
+mod history;
+mod macros;
+mod mark;
+mod normal;
+mod register;
+mod repeat;
 mod search;
+mod selection;
+mod substitute;
+mod syntax;
+mod text_buffer;
+mod text_object;
 mod ui;
+mod unicode;
 mod util;
+mod window;
 mod word;
 
 fn main() -> Result<()> {
@@ -46,10 +67,24 @@ fn main() -> Result<()> {
         .extract()
         .context("Failed to load config")?;
     let mut editor = Editor::new();
+    editor.theme = Theme::from_settings(&config.theme)?;
+    editor.registers.clipboard = Clipboard::new(config.clipboard.clone());
     editor.settings = config;
 
-    if let Some(file) = cli.file {
-        editor.open(&file)?;
+    let (languages, errors) = LanguageRegistry::load(&pe_syntax_dir()?);
+    editor.languages = languages;
+    if let Some(err) = errors.first() {
+        editor.status_message = err.clone();
+    }
+
+    let mut first = None;
+    for file in &cli.files {
+        editor.open(file)?;
+        first.get_or_insert(editor.buffer.id);
+    }
+    // Start out on the first file, like vim does with its argument list
+    if let Some(id) = first {
+        editor.switch_buffer(id)?;
     }
 
     install_panic_hook();
@@ -60,6 +95,12 @@ fn main() -> Result<()> {
             ui(f, &mut editor);
         })?;
         handle_event(&mut editor)?;
+
+        // Copies to the OSC 52 clipboard go straight to the terminal
+        for sequence in editor.registers.clipboard.take_pending() {
+            terminal.backend_mut().write_all(sequence.as_bytes())?;
+            Write::flush(terminal.backend_mut())?;
+        }
     }
 
     terminal.show_cursor()?;
@@ -69,116 +110,420 @@ fn main() -> Result<()> {
 }
 
 fn handle_event(editor: &mut Editor) -> Result<()> {
-    if let Event::Key(key) = event::read()? {
-        if key.kind == event::KeyEventKind::Release {
-            return Ok(());
+    match event::read()? {
+        Event::Key(key) => {
+            if key.kind == event::KeyEventKind::Release {
+                return Ok(());
+            }
+
+            handle_typed_key(key, editor)?;
         }
+        // Text pasted into the terminal arrives in one piece thanks to bracketed paste
+        Event::Paste(text) => {
+            if let CurrentScreen::Editing = editor.current_screen {
+                handle_paste(&text, editor);
+            }
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+// A key typed by the user, which goes into the macro being recorded. Macros it starts are played
+// right after it.
+fn handle_typed_key(key: KeyEvent, editor: &mut Editor) -> Result<()> {
+    // Neither the q starting the recording nor the one ending it are recorded
+    let recording = editor.macros.recording.is_some();
+    editor.macros.plays = 0;
+    editor.bell = false;
+    handle_key(key, editor)?;
+    if let (true, Some((_, keys))) = (recording, &mut editor.macros.recording) {
+        keys.push(key);
+    }
 
+    // The first command that fails stops them all
+    while !editor.bell {
+        let Some(key) = editor.macros.queue.pop_front() else {
+            break;
+        };
         handle_key(key, editor)?;
     }
+    editor.macros.queue.clear();
     Ok(())
 }
 
+// Pasted text goes in as a single edit, but the macro being recorded and `.` get the keys that
+// type it, as if it had been typed
+fn handle_paste(text: &str, editor: &mut Editor) {
+    let keys = paste_keys(editor.mode, text);
+    if let Some((_, recording)) = &mut editor.macros.recording {
+        recording.extend(&keys);
+    }
+    let recording = !editor.repeat.replaying;
+    if recording && is_command_start(editor) {
+        editor
+            .repeat
+            .start(editor.buffer.id, editor.buffer.history.len());
+    }
+    if recording {
+        keys.into_iter().for_each(|key| editor.repeat.key(key));
+    }
+
+    editor.paste_text(text);
+
+    if recording && is_command_start(editor) {
+        editor
+            .repeat
+            .finish(editor.buffer.id, editor.buffer.history.len());
+    }
+}
+
+// Keys that type `text` in `mode`. Outside of Insert mode a paste inserts the text before the
+// cursor and goes back to Normal mode, on the command line it takes newlines as spaces.
+fn paste_keys(mode: Mode, text: &str) -> Vec<KeyEvent> {
+    let text = text.replace("\r\n", "\n").replace('\r', "\n");
+    let key = |code| KeyEvent::new(code, KeyModifiers::NONE);
+    let chars = text.chars().map(|c| match (mode, c) {
+        (Mode::Command | Mode::Search, '\n') => key(KeyCode::Char(' ')),
+        (_, c) => key(KeyCode::Char(c)),
+    });
+    match mode {
+        Mode::Insert | Mode::Command | Mode::Search => chars.collect(),
+        Mode::Normal | Mode::Visual(_) => [key(KeyCode::Esc), key(KeyCode::Char('i'))]
+            .into_iter()
+            .chain(chars)
+            .chain([key(KeyCode::Esc)])
+            .collect(),
+    }
+}
+
 fn handle_key(key: KeyEvent, editor: &mut Editor) -> Result<()> {
+    // A key typed in Normal mode with nothing pending starts a new command, which `.` repeats
+    // if it changes the buffer
+    let recording =
+        !editor.repeat.replaying && matches!(editor.current_screen, CurrentScreen::Editing);
+    if recording && is_command_start(editor) {
+        editor
+            .repeat
+            .start(editor.buffer.id, editor.buffer.history.len());
+    }
+    if recording {
+        editor.repeat.key(key);
+    }
+
     match editor.current_screen {
-        CurrentScreen::Editing => {
-            match key {
-                KeyEvent {
-                    code: KeyCode::Char('q'),
-                    modifiers: KeyModifiers::ALT,
-                    ..
-                } => editor.current_screen = CurrentScreen::Exiting,
-                
-                KeyEvent {
-                    code: KeyCode::Char('k'),
-                    modifiers: KeyModifiers::ALT,
-                    ..
-                } | KeyEvent {
-                    code: KeyCode::Up,
-                    ..
-                } => editor.move_cursor(CursorMove::Up),
-                
-                KeyEvent {
-                    code: KeyCode::Char('j'),
-                    modifiers: KeyModifiers::ALT,
-                    ..
-                } | KeyEvent {
-                    code: KeyCode::Down,
-                    ..
-                } => editor.move_cursor(CursorMove::Down),
-                
-                KeyEvent {
-                    code: KeyCode::Char('h'),
-                    modifiers: KeyModifiers::ALT,
-                    ..
-                } | KeyEvent {
-                    code: KeyCode::Left,
-                    ..
-                } => editor.move_cursor(CursorMove::Left),
-                
-                KeyEvent {
-                    code: KeyCode::Char('l'),
-                    modifiers: KeyModifiers::ALT,
-                    ..
-                } | KeyEvent {
-                    code: KeyCode::Right,
-                    ..
-                } => editor.move_cursor(CursorMove::Right),
-
-                KeyEvent {
-                    code: KeyCode::Char('w'),
-                    modifiers: KeyModifiers::ALT,
-                    ..
-                } => editor.move_cursor(CursorMove::WordStartForward),
-                
-                KeyEvent {
-                    code: KeyCode::Char('b'),
-                    modifiers: KeyModifiers::ALT,
-                    ..
-                } => editor.move_cursor(CursorMove::WordStartBackward),
-                
-                KeyEvent {
-                    code: KeyCode::Char('e'),
-                    modifiers: KeyModifiers::ALT,
-                    ..
-                } => editor.move_cursor(CursorMove::WordEndForward),
-                
-                KeyEvent {
-                    code: KeyCode::Char('H'),
-                    modifiers: KeyModifiers::ALT,
-                    ..
-                } => editor.move_cursor(CursorMove::LineBegin),
-                
-                KeyEvent {
-                    code: KeyCode::Char('L'),
-                    modifiers: KeyModifiers::ALT,
-                    ..
-                } => editor.move_cursor(CursorMove::LineEnd),
-                
-                KeyEvent { code: KeyCode::Enter, .. } => editor.newline_at_cursor(),
-                KeyEvent { code: KeyCode::Backspace, .. } => editor.backspace_at_cursor(),
-                KeyEvent { code: KeyCode::Tab, .. } => editor.insert_tab(),
-                KeyEvent { code: KeyCode::Char(val), .. } => editor.insert_char_at_cursor(val),
-                _ => (),
-            }
-        }
+        CurrentScreen::Editing => match editor.mode {
+            Mode::Normal => handle_normal_key(key, editor)?,
+            Mode::Insert => handle_insert_key(key, editor)?,
+            Mode::Visual(_) => handle_visual_key(key, editor)?,
+            Mode::Command => handle_command_key(key, editor)?,
+            Mode::Search => handle_search_key(key, editor)?,
+        },
         CurrentScreen::Exiting => match key.code {
             KeyCode::Char('y') => {
                 editor.running = false;
             }
-            KeyCode::Char('n') | KeyCode::Char('q') => {
+            KeyCode::Char('n') | KeyCode::Char('q') | KeyCode::Esc => {
                 editor.current_screen = CurrentScreen::Editing;
             }
             _ => {}
         },
+        CurrentScreen::Substitute => match key.code {
+            KeyCode::Char(answer) => editor.answer_substitute(answer),
+            KeyCode::Esc => editor.answer_substitute('q'),
+            _ => {}
+        },
+        CurrentScreen::BufferSwitcher => handle_buffer_switcher_key(key, editor)?,
+        // Any key closes the register and mark lists
+        CurrentScreen::Registers(_) | CurrentScreen::Marks(_) => {
+            editor.current_screen = CurrentScreen::Editing
+        }
+    }
+
+    // Everything done in one insert session, or by one normal mode command, is a single undo
+    // step
+    if editor.mode != Mode::Insert && matches!(editor.current_screen, CurrentScreen::Editing) {
+        editor.commit_history();
+    }
+
+    if recording {
+        match editor.mode {
+            // Ex commands and searches aren't repeated by `.`
+            Mode::Command | Mode::Search => editor.repeat.cancel(),
+            _ if is_command_start(editor) => editor
+                .repeat
+                .finish(editor.buffer.id, editor.buffer.history.len()),
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+// Back in Normal mode with no command half typed
+fn is_command_start(editor: &Editor) -> bool {
+    editor.mode == Mode::Normal && editor.pending_keys.is_empty()
+}
+
+// Replays the last change for `.`, with `count` instead of its own count if given
+fn repeat_change(editor: &mut Editor, count: Option<usize>) -> Result<()> {
+    let Some(mut change) = editor.repeat.last.clone() else {
+        return Ok(());
+    };
+    if let Some(command) = &mut change.command {
+        command.count = count.or(command.count);
+        // After "1p each `.` puts the next older delete, "2p, "3p and so on
+        if let Some(register @ '1'..='8') = command.register {
+            command.register = Some((register as u8 + 1) as char);
+        }
+    }
+    // The `.` itself isn't the change to repeat next time
+    editor.repeat.cancel();
+    editor.repeat.last = Some(change.clone());
+
+    editor.repeat.replaying = true;
+    let mut result = match change.command {
+        Some(command) => editor.run_normal_command(command),
+        None => Ok(()),
+    };
+    for key in change.keys {
+        if result.is_err() {
+            break;
+        }
+        result = handle_key(key, editor);
+    }
+    editor.repeat.replaying = false;
+    result
+}
+
+fn handle_buffer_switcher_key(key: KeyEvent, editor: &mut Editor) -> Result<()> {
+    let ids: Vec<usize> = editor.buffer_list().iter().map(|b| b.id).collect();
+    match key.code {
+        KeyCode::Char('j') | KeyCode::Down => {
+            editor.buffer_switcher_idx = (editor.buffer_switcher_idx + 1).min(ids.len() - 1);
+        }
+        KeyCode::Char('k') | KeyCode::Up => {
+            editor.buffer_switcher_idx = editor.buffer_switcher_idx.saturating_sub(1);
+        }
+        KeyCode::Enter => {
+            editor.current_screen = CurrentScreen::Editing;
+            if let Some(id) = ids.get(editor.buffer_switcher_idx) {
+                if let Err(err) = editor.switch_buffer(*id) {
+                    editor.status_message = err.to_string();
+                }
+            }
+        }
+        KeyCode::Char('d') => {
+            if let Some(id) = ids.get(editor.buffer_switcher_idx) {
+                if let Err(err) = editor.delete_buffer(*id, false) {
+                    editor.status_message = err.to_string();
+                }
+            }
+            let len = editor.buffer_list().len();
+            editor.buffer_switcher_idx = editor.buffer_switcher_idx.min(len - 1);
+        }
+        KeyCode::Char('q') | KeyCode::Esc => {
+            editor.current_screen = CurrentScreen::Editing;
+        }
+        _ => {}
     }
     Ok(())
 }
 
-pub fn init_terminal() -> Result<Terminal<impl Backend>> {
+// The key as it is written in the normal mode grammar, special keys become the keys they stand
+// for and CTRL with a letter the control character it types
+fn grammar_key(key: KeyEvent) -> Option<String> {
+    if key.modifiers.contains(KeyModifiers::CONTROL) {
+        return match key.code {
+            KeyCode::Char(c) if c.is_ascii_alphabetic() => {
+                Some(((c.to_ascii_lowercase() as u8 - b'a' + 1) as char).to_string())
+            }
+            KeyCode::PageDown => Some("gt".to_string()),
+            KeyCode::PageUp => Some("gT".to_string()),
+            _ => None,
+        };
+    }
+
+    let c = match key.code {
+        KeyCode::Char(c) => c,
+        KeyCode::Tab => '\t',
+        KeyCode::Up => 'k',
+        KeyCode::Down => 'j',
+        KeyCode::Left => 'h',
+        KeyCode::Right => 'l',
+        KeyCode::Home => '0',
+        KeyCode::End => '$',
+        _ => return None,
+    };
+    Some(c.to_string())
+}
+
+// Feeds the key to the pending command, returns false when the key is not part of the
+// grammar and needs handling on its own
+fn handle_grammar_key(key: KeyEvent, editor: &mut Editor, parse: fn(&str) -> Parse) -> bool {
+    let Some(keys) = grammar_key(key) else {
+        editor.pending_keys.clear();
+        return false;
+    };
+
+    editor.pending_keys.push_str(&keys);
+    let parse = parse(&editor.pending_keys);
+    // Cleared before running the command, `.` replays keys through here
+    if !matches!(parse, Parse::Pending) {
+        editor.pending_keys.clear();
+    }
+    match parse {
+        Parse::Pending => {}
+        Parse::Invalid => editor.bell = true,
+        Parse::Done(command) => {
+            let result = match command.action {
+                Action::Repeat => repeat_change(editor, command.count),
+                _ => {
+                    if editor.mode == Mode::Normal {
+                        editor.repeat.command(command);
+                    }
+                    editor.run_normal_command(command)
+                }
+            };
+            if let Err(err) = result {
+                editor.status_message = err.to_string();
+                editor.bell = true;
+            }
+            editor.clamp_cursor();
+        }
+        Parse::Unknown => return false,
+    }
+    true
+}
+
+fn handle_normal_key(key: KeyEvent, editor: &mut Editor) -> Result<()> {
+    // q ends the recording of a macro
+    if editor.macros.recording.is_some()
+        && editor.pending_keys.is_empty()
+        && key.code == KeyCode::Char('q')
+        && !key.modifiers.contains(KeyModifiers::CONTROL)
+    {
+        if let Err(err) = editor.stop_recording() {
+            editor.status_message = err.to_string();
+        }
+        return Ok(());
+    }
+
+    handle_grammar_key(key, editor, normal::parse);
+    Ok(())
+}
+
+fn handle_insert_key(key: KeyEvent, editor: &mut Editor) -> Result<()> {
+    match key {
+        KeyEvent { code: KeyCode::Esc, .. } => editor.set_mode(Mode::Normal),
+        KeyEvent { code: KeyCode::Up, .. } => editor.move_cursor(CursorMove::Up),
+        KeyEvent { code: KeyCode::Down, .. } => editor.move_cursor(CursorMove::Down),
+        KeyEvent { code: KeyCode::Left, .. } => editor.move_cursor(CursorMove::Left),
+        KeyEvent { code: KeyCode::Right, .. } => editor.move_cursor(CursorMove::Right),
+        KeyEvent {
+            code: KeyCode::Home,
+            modifiers: KeyModifiers::CONTROL,
+            ..
+        } => editor.move_cursor(CursorMove::Start),
+        KeyEvent {
+            code: KeyCode::End,
+            modifiers: KeyModifiers::CONTROL,
+            ..
+        } => editor.move_cursor(CursorMove::End),
+        KeyEvent { code: KeyCode::Home, .. } => editor.move_cursor(CursorMove::LineBegin),
+        KeyEvent { code: KeyCode::End, .. } => editor.move_cursor(CursorMove::LineEnd),
+        KeyEvent { code: KeyCode::Enter, .. } => editor.newline_at_cursor(),
+        KeyEvent { code: KeyCode::Backspace, .. } => editor.backspace_at_cursor(),
+        KeyEvent { code: KeyCode::Tab, .. } => editor.insert_tab(),
+        KeyEvent {
+            code: KeyCode::Char(val),
+            modifiers: KeyModifiers::NONE | KeyModifiers::SHIFT,
+            ..
+        } => editor.insert_char_at_cursor(val),
+        _ => (),
+    }
+    Ok(())
+}
+
+fn handle_visual_key(key: KeyEvent, editor: &mut Editor) -> Result<()> {
+    if handle_grammar_key(key, editor, normal::parse_visual) {
+        return Ok(());
+    }
+
+    let block = editor.mode == Mode::Visual(VisualKind::Block);
+    match key {
+        KeyEvent {
+            code: KeyCode::Char('v'),
+            modifiers: KeyModifiers::CONTROL,
+            ..
+        } => editor.toggle_visual(VisualKind::Block),
+        KeyEvent { code: KeyCode::Esc, .. } => editor.set_mode(Mode::Normal),
+        KeyEvent { code: KeyCode::Char('v'), .. } => editor.toggle_visual(VisualKind::Char),
+        KeyEvent { code: KeyCode::Char('V'), .. } => editor.toggle_visual(VisualKind::Line),
+        KeyEvent { code: KeyCode::Char('o'), .. } => editor.swap_selection_ends(false),
+        KeyEvent { code: KeyCode::Char('O'), .. } => editor.swap_selection_ends(true),
+        // Typing on every row of the block
+        KeyEvent { code: KeyCode::Char('I'), .. } if block => editor.insert_in_block(false),
+        KeyEvent { code: KeyCode::Char('A'), .. } if block => editor.insert_in_block(true),
+        KeyEvent { code: KeyCode::Char(':'), .. } => {
+            editor.set_mode(Mode::Command);
+            editor.command = "'<,'>".to_string();
+            editor.move_command_cursor(CursorMove::LineEnd);
+        }
+        _ => (),
+    }
+    Ok(())
+}
+
+fn handle_command_key(key: KeyEvent, editor: &mut Editor) -> Result<()> {
+    match key.code {
+        KeyCode::Esc => editor.set_mode(Mode::Normal),
+        KeyCode::Enter => editor.execute_command(),
+        _ => edit_command_line(key, editor),
+    }
+    Ok(())
+}
+
+fn handle_search_key(key: KeyEvent, editor: &mut Editor) -> Result<()> {
+    match key.code {
+        KeyCode::Esc => editor.cancel_search(),
+        KeyCode::Enter => editor.execute_current_search(),
+        _ => {
+            edit_command_line(key, editor);
+            if editor.mode == Mode::Search {
+                editor.incremental_search();
+            }
+        }
+    }
+    Ok(())
+}
+
+fn edit_command_line(key: KeyEvent, editor: &mut Editor) {
+    match key {
+        KeyEvent { code: KeyCode::Left, .. } => editor.move_command_cursor(CursorMove::Left),
+        KeyEvent { code: KeyCode::Right, .. } => editor.move_command_cursor(CursorMove::Right),
+        KeyEvent { code: KeyCode::Home, .. } => editor.move_command_cursor(CursorMove::LineBegin),
+        KeyEvent { code: KeyCode::End, .. } => editor.move_command_cursor(CursorMove::LineEnd),
+        KeyEvent { code: KeyCode::Up, .. } => editor.command_history_prev(),
+        KeyEvent { code: KeyCode::Down, .. } => editor.command_history_next(),
+        KeyEvent { code: KeyCode::Backspace, .. } => editor.backspace_at_command_cursor(),
+        KeyEvent {
+            code: KeyCode::Char(val),
+            modifiers: KeyModifiers::NONE | KeyModifiers::SHIFT,
+            ..
+        } => editor.insert_char_at_command_cursor(val),
+        _ => (),
+    }
+}
+
+pub fn init_terminal() -> Result<Terminal<impl Backend + Write>> {
     enable_raw_mode()?;
     let mut stderr = io::stderr();
-    execute!(stderr, EnterAlternateScreen, EnableMouseCapture)?;
+    execute!(
+        stderr,
+        EnterAlternateScreen,
+        EnableMouseCapture,
+        EnableBracketedPaste
+    )?;
 
     let backend = CrosstermBackend::new(stdout());
     let terminal = Terminal::new(backend)?;
@@ -189,6 +534,7 @@ pub fn init_terminal() -> Result<Terminal<impl Backend>> {
 pub fn restore_terminal() -> Result<()> {
     io::stdout().execute(LeaveAlternateScreen)?;
     io::stdout().execute(DisableMouseCapture)?;
+    io::stdout().execute(DisableBracketedPaste)?;
     disable_raw_mode()?;
     Ok(())
 }
@@ -198,6 +544,7 @@ pub fn install_panic_hook() {
     set_hook(Box::new(move |panic_info| {
         io::stdout().execute(LeaveAlternateScreen).unwrap();
         io::stdout().execute(DisableMouseCapture).unwrap();
+        io::stdout().execute(DisableBracketedPaste).unwrap();
         disable_raw_mode().unwrap();
         original_hook(panic_info);
     }));
@@ -0,0 +1,104 @@
+use std::collections::HashMap;
+
+use crate::{cursor::Cursor, history::Edit, text_buffer::TextBuffer};
+
+const JUMPLIST_MAX: usize = 100;
+
+// Marks a-z belong to a buffer, A-Z are global and kept by the buffer they were set in. ' (also
+// written `) is where the cursor was before the latest jump.
+pub fn is_settable(name: char) -> bool {
+    name.is_ascii_alphabetic() || name == '\'' || name == '`'
+}
+
+// Marks that can be jumped to, '< and '> being the last Visual selection
+pub fn is_valid(name: char) -> bool {
+    is_settable(name) || name == '<' || name == '>'
+}
+
+pub fn is_global(name: char) -> bool {
+    name.is_ascii_uppercase()
+}
+
+// Where the marks of `text` end up after `edit`, as char indices. A mark moves along with the
+// text it is on, and is removed when its whole line is deleted.
+pub fn adjust(marks: &HashMap<char, Cursor>, text: &TextBuffer, edit: &Edit) -> Vec<(char, usize)> {
+    marks
+        .iter()
+        .filter_map(|(name, mark)| {
+            let idx = text.pos_to_char(*mark);
+            let idx = match edit {
+                Edit::Insert { at, text: inserted } if idx >= *at => idx + inserted.chars().count(),
+                Edit::Insert { .. } => idx,
+                Edit::Delete { at, text: deleted } => {
+                    let end = at + deleted.chars().count();
+                    if idx >= end {
+                        idx - (end - at)
+                    } else if idx < *at {
+                        idx
+                    } else if deletes_line(text, mark.y, *at, end) {
+                        return None;
+                    } else {
+                        *at
+                    }
+                }
+            };
+            Some((*name, idx))
+        })
+        .collect()
+}
+
+// Whether deleting `at..end` takes line `y` with it, along with the line break joining it to
+// the rest
+fn deletes_line(text: &TextBuffer, y: usize, at: usize, end: usize) -> bool {
+    let start = text.pos_to_char((0, y).into());
+    match y + 1 < text.len_lines() {
+        true => at <= start && end >= text.pos_to_char((0, y + 1).into()),
+        false => at < start && end >= text.len_chars(),
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct Jump {
+    pub buffer_id: usize,
+    pub cursor: Cursor,
+}
+
+// Positions jumped away from, oldest first, gone through with CTRL-O and CTRL-I
+#[derive(Default)]
+pub struct JumpList {
+    jumps: Vec<Jump>,
+    // Entry CTRL-O and CTRL-I last went to, the length of the list when they haven't been used
+    // since the last jump
+    idx: usize,
+}
+
+impl JumpList {
+    // Only one entry is kept per line
+    pub fn push(&mut self, jump: Jump) {
+        self.jumps
+            .retain(|other| other.buffer_id != jump.buffer_id || other.cursor.y != jump.cursor.y);
+        self.jumps.push(jump);
+        if self.jumps.len() > JUMPLIST_MAX {
+            self.jumps.remove(0);
+        }
+        self.idx = self.jumps.len();
+    }
+
+    // The entry `offset` away from the current one. Going back from the newest position first
+    // adds it to the list, so CTRL-I can return to it.
+    pub fn go(&mut self, current: Jump, offset: isize) -> Option<Jump> {
+        if offset < 0 && self.idx == self.jumps.len() {
+            self.push(current);
+            self.idx = self.jumps.len() - 1;
+        }
+        let idx = self.idx.checked_add_signed(offset)?;
+        let jump = *self.jumps.get(idx)?;
+        self.idx = idx;
+        Some(jump)
+    }
+
+    pub fn remove_buffer(&mut self, buffer_id: usize) {
+        self.jumps.retain(|jump| jump.buffer_id != buffer_id);
+        self.idx = self.idx.min(self.jumps.len());
+    }
+}
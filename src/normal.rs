@@ -0,0 +1,597 @@
+use crate::{
+    cursor::Cursor,
+    editor::CursorMove,
+    mark, register,
+    selection::VisualKind,
+    text_object::{ObjectKind, TextObject},
+};
+
+// Normal mode commands are `[count] operator [count] motion`, `[count] motion` or one of a few
+// plain key sequences, any of them can start with `"x` to use register x. q and @ take a
+// register name after them instead, m a mark name and f, F, t and T a character. Keys are
+// matched as typed, with the arrow keys, Home and End already turned into h, j, k, l, 0 and $,
+// and CTRL with a letter into the control character it types, CTRL-D being \x04.
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Operator {
+    Delete,
+    Change,
+    Yank,
+    Indent,
+    Outdent,
+    Lowercase,
+    Uppercase,
+    ToggleCase,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Target {
+    Motion(CursorMove),
+    Object(TextObject),
+    // The operator typed twice, e.g. dd or gUU, acts on whole lines
+    Lines,
+    // The Visual mode selection, or every line it is on for the likes of D and Y
+    Selection,
+    SelectionLines,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Action {
+    Motion(CursorMove),
+    Operator(Operator, Target),
+    // A text object typed in Visual mode, which selects it
+    Select(TextObject),
+    // p and P
+    Put { before: bool },
+    TabNext,
+    TabPrev,
+    // `.`, a count replaces the one of the repeated command
+    Repeat,
+    // q and @ with a register
+    Record(char),
+    Play(char),
+    // m with a mark
+    SetMark(char),
+    // gv
+    Reselect,
+    // i, a, I, A, o and O, the count repeats the text typed
+    Insert(InsertAt),
+    // v, V and CTRL-V
+    Visual(VisualKind),
+    // / and ?
+    Search { backward: bool },
+    // n and N
+    SearchNext { reverse: bool },
+    Undo,
+    Redo,
+    // CTRL-D and CTRL-U, a count sets how far they scroll from then on
+    Scroll { down: bool },
+    // CTRL-O and CTRL-I
+    JumpOlder,
+    JumpNewer,
+    // CTRL-W and the key after it
+    Window(char),
+    // `:`, a count puts the range of that many lines on the command line
+    CommandLine,
+}
+
+// Where i, a, I, A, o and O start Insert mode
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum InsertAt {
+    Cursor,
+    AfterCursor,
+    LineBegin,
+    LineEnd,
+    LineBelow,
+    LineAbove,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct NormalCommand {
+    pub count: Option<usize>,
+    pub register: Option<char>,
+    pub action: Action,
+}
+
+pub enum Parse {
+    // More keys are needed, e.g. after `d` or `2g`
+    Pending,
+    Done(NormalCommand),
+    // The keys started a command but can't finish it, e.g. `dq`
+    Invalid,
+    // A single key that isn't part of the grammar, left to the other bindings
+    Unknown,
+}
+
+// Text an operator acts on. Character ranges are exclusive at the end, line ranges inclusive. A
+// block is the display columns start.x..end.x of the lines start.y..=end.y.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum OperatorRange {
+    Chars(Cursor, Cursor),
+    Lines(usize, usize),
+    Block(Cursor, Cursor),
+}
+
+// Every motion here also works after each of the operators
+const MOTIONS: &[(&str, CursorMove)] = &[
+    ("h", CursorMove::Left),
+    ("j", CursorMove::Down),
+    ("k", CursorMove::Up),
+    ("l", CursorMove::Right),
+    ("w", CursorMove::WordStartForward),
+    ("b", CursorMove::WordStartBackward),
+    ("e", CursorMove::WordEndForward),
+    ("ge", CursorMove::WordEndBackward),
+    ("W", CursorMove::BigWordStartForward),
+    ("B", CursorMove::BigWordStartBackward),
+    ("E", CursorMove::BigWordEndForward),
+    ("gE", CursorMove::BigWordEndBackward),
+    (";", CursorMove::RepeatFind),
+    (",", CursorMove::RepeatFindReverse),
+    ("0", CursorMove::LineBegin),
+    ("$", CursorMove::LineEnd),
+    ("gg", CursorMove::Start),
+    ("G", CursorMove::End),
+];
+
+// A mark jump, `'x` to the line of mark x or ``x` to its exact position
+fn mark_motion(keys: &str) -> Option<CursorMove> {
+    let mut chars = keys.chars();
+    let key = chars.next()?;
+    let name = chars.next().filter(|name| mark::is_valid(*name))?;
+    if chars.next().is_some() {
+        return None;
+    }
+    match key {
+        '\'' => Some(CursorMove::MarkLine(name)),
+        '`' => Some(CursorMove::Mark(name)),
+        _ => None,
+    }
+}
+
+// f, F, t or T and the character to find, e.g. `f,` or `dt)`
+fn find_motion(keys: &str) -> Option<CursorMove> {
+    let mut chars = keys.chars();
+    let key = chars.next()?;
+    let c = chars.next()?;
+    if chars.next().is_some() {
+        return None;
+    }
+    match key {
+        'f' => Some(CursorMove::FindForward(c)),
+        'F' => Some(CursorMove::FindBackward(c)),
+        't' => Some(CursorMove::TillForward(c)),
+        'T' => Some(CursorMove::TillBackward(c)),
+        _ => None,
+    }
+}
+
+fn motion(keys: &str) -> Option<CursorMove> {
+    lookup(MOTIONS, keys)
+        .or_else(|| mark_motion(keys))
+        .or_else(|| find_motion(keys))
+}
+
+fn is_motion_prefix(keys: &str) -> bool {
+    is_prefix(MOTIONS, keys) || matches!(keys, "'" | "`" | "f" | "F" | "t" | "T")
+}
+
+// Typed after `i` for the inner object or `a` for the one around it, e.g. `ci(` or `dap`
+const TEXT_OBJECTS: &[(char, ObjectKind)] = &[
+    ('w', ObjectKind::Word),
+    ('W', ObjectKind::BigWord),
+    ('s', ObjectKind::Sentence),
+    ('p', ObjectKind::Paragraph),
+    ('"', ObjectKind::Quote('"')),
+    ('\'', ObjectKind::Quote('\'')),
+    ('`', ObjectKind::Quote('`')),
+    ('(', ObjectKind::Bracket('(', ')')),
+    (')', ObjectKind::Bracket('(', ')')),
+    ('b', ObjectKind::Bracket('(', ')')),
+    ('{', ObjectKind::Bracket('{', '}')),
+    ('}', ObjectKind::Bracket('{', '}')),
+    ('B', ObjectKind::Bracket('{', '}')),
+    ('[', ObjectKind::Bracket('[', ']')),
+    (']', ObjectKind::Bracket('[', ']')),
+    ('<', ObjectKind::Bracket('<', '>')),
+    ('>', ObjectKind::Bracket('<', '>')),
+    ('t', ObjectKind::Tag),
+];
+
+const OPERATORS: &[(&str, Operator)] = &[
+    ("d", Operator::Delete),
+    ("c", Operator::Change),
+    ("y", Operator::Yank),
+    (">", Operator::Indent),
+    ("<", Operator::Outdent),
+    ("gu", Operator::Lowercase),
+    ("gU", Operator::Uppercase),
+    ("g~", Operator::ToggleCase),
+];
+
+const COMMANDS: &[(&str, Action)] = &[
+    ("p", Action::Put { before: false }),
+    ("P", Action::Put { before: true }),
+    ("gt", Action::TabNext),
+    ("gT", Action::TabPrev),
+    (".", Action::Repeat),
+    ("gv", Action::Reselect),
+    ("i", Action::Insert(InsertAt::Cursor)),
+    ("a", Action::Insert(InsertAt::AfterCursor)),
+    ("I", Action::Insert(InsertAt::LineBegin)),
+    ("A", Action::Insert(InsertAt::LineEnd)),
+    ("o", Action::Insert(InsertAt::LineBelow)),
+    ("O", Action::Insert(InsertAt::LineAbove)),
+    ("v", Action::Visual(VisualKind::Char)),
+    ("V", Action::Visual(VisualKind::Line)),
+    ("\x16", Action::Visual(VisualKind::Block)),
+    ("/", Action::Search { backward: false }),
+    ("?", Action::Search { backward: true }),
+    ("n", Action::SearchNext { reverse: false }),
+    ("N", Action::SearchNext { reverse: true }),
+    ("u", Action::Undo),
+    ("\x12", Action::Redo),
+    ("\x04", Action::Scroll { down: true }),
+    ("\x15", Action::Scroll { down: false }),
+    ("\x0f", Action::JumpOlder),
+    // CTRL-I, which is Tab
+    ("\t", Action::JumpNewer),
+    (":", Action::CommandLine),
+];
+
+// Operators act on the selection in Visual mode, where they take no motion
+const VISUAL_OPERATORS: &[(&str, Operator)] = &[
+    ("d", Operator::Delete),
+    ("x", Operator::Delete),
+    ("c", Operator::Change),
+    ("s", Operator::Change),
+    ("y", Operator::Yank),
+    (">", Operator::Indent),
+    ("<", Operator::Outdent),
+    ("u", Operator::Lowercase),
+    ("U", Operator::Uppercase),
+    ("~", Operator::ToggleCase),
+    ("gu", Operator::Lowercase),
+    ("gU", Operator::Uppercase),
+    ("g~", Operator::ToggleCase),
+];
+
+// The same on every line of the selection, whatever its kind
+const VISUAL_LINE_OPERATORS: &[(&str, Operator)] = &[
+    ("X", Operator::Delete),
+    ("D", Operator::Delete),
+    ("C", Operator::Change),
+    ("S", Operator::Change),
+    ("R", Operator::Change),
+    ("Y", Operator::Yank),
+];
+
+// Shorthands for an operator and motion
+const ALIASES: &[(&str, &str)] = &[
+    ("x", "dl"),
+    ("X", "dh"),
+    ("D", "d$"),
+    ("C", "c$"),
+    ("s", "cl"),
+    ("S", "cc"),
+    ("Y", "yy"),
+];
+
+fn lookup<T: Copy>(table: &[(&str, T)], keys: &str) -> Option<T> {
+    table
+        .iter()
+        .find(|(name, _)| *name == keys)
+        .map(|(_, value)| *value)
+}
+
+fn is_prefix<T>(table: &[(&str, T)], keys: &str) -> bool {
+    table
+        .iter()
+        .any(|(name, _)| name.len() > keys.len() && name.starts_with(keys))
+}
+
+fn text_object(keys: &str) -> Option<TextObject> {
+    let mut chars = keys.chars();
+    let around = match chars.next()? {
+        'i' => false,
+        'a' => true,
+        _ => return None,
+    };
+    let key = chars.next()?;
+    if chars.next().is_some() {
+        return None;
+    }
+    let (_, kind) = TEXT_OBJECTS.iter().find(|(name, _)| *name == key)?;
+    Some(TextObject {
+        kind: *kind,
+        around,
+    })
+}
+
+// A leading 0 is the motion, not a count
+fn split_count(keys: &str) -> (Option<usize>, &str) {
+    if keys.starts_with('0') {
+        return (None, keys);
+    }
+    let len = keys
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(keys.len());
+    // Too many digits for a usize make the largest count rather than none
+    let count = match len {
+        0 => None,
+        _ => Some(keys[..len].parse().unwrap_or(usize::MAX)),
+    };
+    (count, &keys[len..])
+}
+
+// Counts before the operator and before the motion multiply, 2d3w deletes six words
+fn multiply(a: Option<usize>, b: Option<usize>) -> Option<usize> {
+    match (a, b) {
+        (Some(a), Some(b)) => Some(a.saturating_mul(b)),
+        (a, b) => a.or(b),
+    }
+}
+
+// q{register} to record a macro, which can't go into the read-only registers, and @{register}
+// to play one, @@ playing the last one again. m{mark} sets a mark.
+fn named_command(count: Option<usize>, keys: &str) -> Option<Parse> {
+    let mut chars = keys.chars();
+    let key = chars.next().filter(|key| matches!(key, 'q' | '@' | 'm'))?;
+    let Some(name) = chars.next() else {
+        return Some(Parse::Pending);
+    };
+    let action = match key {
+        'q' if name.is_ascii_alphanumeric() || name == '"' => Action::Record(name),
+        '@' if name == '@' || register::is_valid(name) => Action::Play(name),
+        'm' if mark::is_settable(name) => Action::SetMark(name),
+        _ => return Some(Parse::Invalid),
+    };
+    Some(Parse::Done(NormalCommand {
+        count,
+        register: None,
+        action,
+    }))
+}
+
+// CTRL-W and a window command, which can be typed with CTRL held as well
+fn window_command(count: Option<usize>, keys: &str) -> Option<Parse> {
+    let mut chars = keys.strip_prefix('\x17')?.chars();
+    let Some(key) = chars.next() else {
+        return Some(Parse::Pending);
+    };
+    let key = match key {
+        '\x01'..='\x1a' => (key as u8 + b'a' - 1) as char,
+        key => key,
+    };
+    Some(Parse::Done(NormalCommand {
+        count,
+        register: None,
+        action: Action::Window(key),
+    }))
+}
+
+// `"x` before a command parsed by `parse`, which then uses register x
+fn register_prefix(count: Option<usize>, keys: &str, parse: fn(&str) -> Parse) -> Option<Parse> {
+    let keys = keys.strip_prefix('"')?;
+    let mut chars = keys.chars();
+    let Some(name) = chars.next() else {
+        return Some(Parse::Pending);
+    };
+    if !register::is_valid(name) {
+        return Some(Parse::Invalid);
+    }
+    let parse = match parse(chars.as_str()) {
+        Parse::Done(command) => Parse::Done(NormalCommand {
+            count: multiply(count, command.count),
+            register: Some(name),
+            ..command
+        }),
+        Parse::Pending => Parse::Pending,
+        _ => Parse::Invalid,
+    };
+    Some(parse)
+}
+
+pub fn parse(keys: &str) -> Parse {
+    let (count, keys) = split_count(keys);
+    if keys.is_empty() {
+        return Parse::Pending;
+    }
+
+    if let Some(parse) = register_prefix(count, keys, parse) {
+        return parse;
+    }
+
+    if let Some(parse) = named_command(count, keys) {
+        return parse;
+    }
+
+    if let Some(parse) = window_command(count, keys) {
+        return parse;
+    }
+
+    if let Some(expansion) = lookup(ALIASES, keys) {
+        return match parse(expansion) {
+            Parse::Done(command) => Parse::Done(NormalCommand {
+                count: multiply(count, command.count),
+                ..command
+            }),
+            parse => parse,
+        };
+    }
+
+    if let Some((name, operator)) = OPERATORS.iter().find(|(name, _)| keys.starts_with(name)) {
+        let (motion_count, rest) = split_count(&keys[name.len()..]);
+        let count = multiply(count, motion_count);
+        // dd, and for the two key operators both gUU and gUgU
+        let target = if rest == *name || rest == &name[name.len() - 1..] {
+            Target::Lines
+        } else if let Some(motion) = motion(rest) {
+            Target::Motion(motion)
+        } else if let Some(object) = text_object(rest) {
+            Target::Object(object)
+        } else if rest.is_empty()
+            || is_motion_prefix(rest)
+            || name.starts_with(rest)
+            || rest == "i"
+            || rest == "a"
+        {
+            return Parse::Pending;
+        } else {
+            return Parse::Invalid;
+        };
+        let action = Action::Operator(*operator, target);
+        return Parse::Done(NormalCommand {
+            count,
+            register: None,
+            action,
+        });
+    }
+
+    if let Some(motion) = motion(keys) {
+        let action = Action::Motion(motion);
+        return Parse::Done(NormalCommand {
+            count,
+            register: None,
+            action,
+        });
+    }
+    if let Some(action) = lookup(COMMANDS, keys) {
+        return Parse::Done(NormalCommand {
+            count,
+            register: None,
+            action,
+        });
+    }
+    if is_motion_prefix(keys) || is_prefix(OPERATORS, keys) || is_prefix(COMMANDS, keys) {
+        return Parse::Pending;
+    }
+    match keys.chars().count() {
+        1 => Parse::Unknown,
+        _ => Parse::Invalid,
+    }
+}
+
+// Motions, text objects and the operators acting on the selection, for Visual mode
+pub fn parse_visual(keys: &str) -> Parse {
+    let (count, keys) = split_count(keys);
+    if keys.is_empty() {
+        return Parse::Pending;
+    }
+    if let Some(parse) = register_prefix(count, keys, parse_visual) {
+        return parse;
+    }
+
+    let action = if let Some(motion) = motion(keys) {
+        Action::Motion(motion)
+    } else if let Some(object) = text_object(keys) {
+        Action::Select(object)
+    } else if let Some(operator) = lookup(VISUAL_OPERATORS, keys) {
+        Action::Operator(operator, Target::Selection)
+    } else if let Some(operator) = lookup(VISUAL_LINE_OPERATORS, keys) {
+        Action::Operator(operator, Target::SelectionLines)
+    } else if keys == "gv" {
+        Action::Reselect
+    } else if is_motion_prefix(keys)
+        || is_prefix(VISUAL_OPERATORS, keys)
+        || keys == "i"
+        || keys == "a"
+    {
+        return Parse::Pending;
+    } else {
+        return match keys.chars().count() {
+            1 => Parse::Unknown,
+            _ => Parse::Invalid,
+        };
+    };
+    Parse::Done(NormalCommand {
+        count,
+        register: None,
+        action,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn done(keys: &str) -> (Option<usize>, Option<char>, Action) {
+        match parse(keys) {
+            Parse::Done(command) => (command.count, command.register, command.action),
+            _ => panic!("{keys} is not a complete command"),
+        }
+    }
+
+    fn operator(keys: &str) -> (Option<usize>, Operator, Target) {
+        match done(keys) {
+            (count, _, Action::Operator(operator, target)) => (count, operator, target),
+            (_, _, action) => panic!("{keys} is {action:?}"),
+        }
+    }
+
+    #[test]
+    fn counts() {
+        assert_eq!(done("w").0, None);
+        assert_eq!(
+            done("3w"),
+            (Some(3), None, Action::Motion(CursorMove::WordStartForward))
+        );
+        // 0 is a motion, not the start of a count
+        assert_eq!(done("0").2, Action::Motion(CursorMove::LineBegin));
+        assert_eq!(done("10j").0, Some(10));
+        assert_eq!(done("2p"), (Some(2), None, Action::Put { before: false }));
+        assert_eq!(done("4\x04").2, Action::Scroll { down: true });
+        assert!(matches!(parse("12"), Parse::Pending));
+    }
+
+    #[test]
+    fn operators() {
+        let word = Target::Motion(CursorMove::WordStartForward);
+        assert_eq!(operator("dw"), (None, Operator::Delete, word));
+        assert_eq!(operator("dd"), (None, Operator::Delete, Target::Lines));
+        assert_eq!(operator("gUU"), (None, Operator::Uppercase, Target::Lines));
+        assert_eq!(operator("gUgU"), (None, Operator::Uppercase, Target::Lines));
+        assert_eq!(operator("g~w"), (None, Operator::ToggleCase, word));
+        // Counts before the operator and before the motion multiply
+        assert_eq!(operator("2d3w"), (Some(6), Operator::Delete, word));
+        assert_eq!(operator("d3w").0, Some(3));
+        assert_eq!(
+            operator("ci(").2,
+            Target::Object(TextObject {
+                kind: ObjectKind::Bracket('(', ')'),
+                around: false,
+            })
+        );
+        assert_eq!(
+            operator("dt)").2,
+            Target::Motion(CursorMove::TillForward(')'))
+        );
+        assert_eq!(done("\"ayy"), (None, Some('a'), done("yy").2));
+        assert_eq!(done("2\"a3yy").0, Some(6));
+    }
+
+    #[test]
+    fn incomplete_and_invalid() {
+        for keys in ["d", "d2", "gU", "g", "\"", "\"a", "di", "f", "dt", "\x17"] {
+            assert!(matches!(parse(keys), Parse::Pending), "{keys}");
+        }
+        for keys in ["dq", "d\x04", "gUx"] {
+            assert!(matches!(parse(keys), Parse::Invalid), "{keys}");
+        }
+        assert!(matches!(parse("Z"), Parse::Unknown));
+    }
+
+    #[test]
+    fn huge_counts() {
+        let huge = usize::MAX.to_string();
+        assert_eq!(done(&format!("{huge}j")).0, Some(usize::MAX));
+        // Multiplied counts saturate
+        let keys = format!("{huge}d{huge}w");
+        assert_eq!(operator(&keys).0, Some(usize::MAX));
+        assert_eq!(operator("99999999999d99999999999j").0, Some(usize::MAX));
+        // Too many digits for a usize still make a count
+        let keys = format!("{huge}0j");
+        assert_eq!(done(&keys).0, Some(usize::MAX));
+        assert_eq!(operator(&format!("d{huge}9k")).0, Some(usize::MAX));
+    }
+}
@@ -0,0 +1,152 @@
+use anyhow::{anyhow, Result};
+
+use crate::clipboard::Clipboard;
+
+#[derive(Clone, Copy, Default, PartialEq, Eq, Debug)]
+pub enum RegisterKind {
+    #[default]
+    Charwise,
+    // Whole lines, the text always ends in a newline
+    Linewise,
+    // A rectangle, one line of text per row
+    Blockwise,
+}
+
+impl RegisterKind {
+    // Type column of :registers
+    pub fn letter(&self) -> char {
+        match self {
+            RegisterKind::Charwise => 'c',
+            RegisterKind::Linewise => 'l',
+            RegisterKind::Blockwise => 'b',
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct Register {
+    pub text: String,
+    pub kind: RegisterKind,
+}
+
+impl Register {
+    pub fn new(text: String, kind: RegisterKind) -> Self {
+        Self { text, kind }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.text.is_empty()
+    }
+}
+
+// The writable registers. The read-only ones (". % :) come from the editor state and the
+// clipboard has to be asked, see Editor::register.
+#[derive(Default)]
+pub struct Registers {
+    unnamed: Register,
+    // "0 holds the last yank, "1 to "9 the last deletes of whole lines or more than a line
+    numbered: [Register; 10],
+    // "a to "z, "A to "Z append to them
+    named: [Register; 26],
+    // "- holds deletes within a line
+    small_delete: Register,
+    // "+ and "*, both the system clipboard
+    pub clipboard: Clipboard,
+    pub last_insert: String,
+    pub last_command: String,
+}
+
+const READ_ONLY: &[char] = &['.', '%', ':'];
+
+pub fn is_valid(name: char) -> bool {
+    name.is_ascii_alphanumeric() || "\"-_+*".contains(name) || READ_ONLY.contains(&name)
+}
+
+impl Registers {
+    pub fn get(&self, name: char) -> Option<&Register> {
+        match name {
+            '"' => Some(&self.unnamed),
+            '0'..='9' => Some(&self.numbered[name as usize - '0' as usize]),
+            'a'..='z' | 'A'..='Z' => {
+                Some(&self.named[name.to_ascii_lowercase() as usize - 'a' as usize])
+            }
+            '-' => Some(&self.small_delete),
+            _ => None,
+        }
+    }
+
+    pub fn yank(&mut self, name: Option<char>, register: Register) -> Result<()> {
+        match name {
+            None | Some('"') => {
+                self.numbered[0] = register.clone();
+                self.unnamed = register;
+                Ok(())
+            }
+            Some(name) => self.set(name, register),
+        }
+    }
+
+    // Without a register name, deletes within a line go to "- and others shift "1 to "9
+    pub fn delete(&mut self, name: Option<char>, register: Register) -> Result<()> {
+        match name {
+            None | Some('"') => {
+                if register.kind == RegisterKind::Charwise && !register.text.contains('\n') {
+                    self.small_delete = register.clone();
+                } else {
+                    self.numbered[1..].rotate_right(1);
+                    self.numbered[1] = register.clone();
+                }
+                self.unnamed = register;
+                Ok(())
+            }
+            Some(name) => self.set(name, register),
+        }
+    }
+
+    // Macros are recorded into a register without it becoming the unnamed one
+    pub fn record(&mut self, name: char, text: String) -> Result<()> {
+        let unnamed = self.unnamed.clone();
+        self.set(name, Register::new(text, RegisterKind::Charwise))?;
+        self.unnamed = unnamed;
+        Ok(())
+    }
+
+    fn set(&mut self, name: char, register: Register) -> Result<()> {
+        let target = match name {
+            '_' => return Ok(()),
+            'A'..='Z' => {
+                let target = &mut self.named[name as usize - 'A' as usize];
+                append(target, register);
+                self.unnamed = target.clone();
+                return Ok(());
+            }
+            'a'..='z' => &mut self.named[name as usize - 'a' as usize],
+            '0'..='9' => &mut self.numbered[name as usize - '0' as usize],
+            '-' => &mut self.small_delete,
+            '+' | '*' => {
+                self.clipboard.set(register.clone())?;
+                self.unnamed = register;
+                return Ok(());
+            }
+            _ => return Err(anyhow!("E354: Invalid register name: '{}'", name)),
+        };
+        *target = register.clone();
+        self.unnamed = register;
+        Ok(())
+    }
+}
+
+// Appending lines to text, or text to lines, gives lines
+fn append(target: &mut Register, register: Register) {
+    if register.kind == RegisterKind::Linewise && target.kind != RegisterKind::Linewise {
+        if !target.text.is_empty() {
+            target.text.push('\n');
+        }
+        target.kind = RegisterKind::Linewise;
+    } else if target.kind == RegisterKind::Linewise && !register.text.ends_with('\n') {
+        target.text.push_str(&register.text);
+        target.text.push('\n');
+        return;
+    }
+    target.text.push_str(&register.text);
+}
@@ -0,0 +1,66 @@
+use crossterm::event::KeyEvent;
+
+use crate::normal::NormalCommand;
+
+// A change as `.` replays it: the normal mode command when it came from the grammar, and the
+// keys typed after it, e.g. the text of the Insert mode session it started and the Esc ending it.
+// Commands outside the grammar, like `o`, are only keys.
+#[derive(Clone, Debug, Default)]
+pub struct Change {
+    pub command: Option<NormalCommand>,
+    pub keys: Vec<KeyEvent>,
+}
+
+struct Recording {
+    change: Change,
+    buffer_id: usize,
+    // Undo revisions of the buffer when the command started, a new one means it changed the text
+    revisions: usize,
+}
+
+// Records the command being typed until it is complete, and keeps it for `.` if it turned out
+// to change the buffer
+#[derive(Default)]
+pub struct ChangeRecorder {
+    current: Option<Recording>,
+    pub last: Option<Change>,
+    // Set while `.` replays the last change, which isn't recorded again
+    pub replaying: bool,
+}
+
+impl ChangeRecorder {
+    pub fn start(&mut self, buffer_id: usize, revisions: usize) {
+        self.current = Some(Recording {
+            change: Change::default(),
+            buffer_id,
+            revisions,
+        });
+    }
+
+    pub fn key(&mut self, key: KeyEvent) {
+        if let Some(recording) = &mut self.current {
+            recording.change.keys.push(key);
+        }
+    }
+
+    // The keys typed so far made up `command`, which replays them with its count replaceable
+    pub fn command(&mut self, command: NormalCommand) {
+        if let Some(recording) = &mut self.current {
+            recording.change.command = Some(command);
+            recording.change.keys.clear();
+        }
+    }
+
+    pub fn cancel(&mut self) {
+        self.current = None;
+    }
+
+    pub fn finish(&mut self, buffer_id: usize, revisions: usize) {
+        let Some(recording) = self.current.take() else {
+            return;
+        };
+        if recording.buffer_id == buffer_id && revisions > recording.revisions {
+            self.last = Some(recording.change);
+        }
+    }
+}
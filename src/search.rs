@@ -1,30 +1,151 @@
+use anyhow::{anyhow, Result};
+use regex::{Regex, RegexBuilder};
+
+use crate::{cursor::Cursor, text_buffer::TextBuffer, unicode::grapheme_count};
+
+#[derive(Clone, Copy, Default, PartialEq, Eq)]
+pub enum SearchDirection {
+    #[default]
+    Forward,
+    Backward,
+}
+
+impl SearchDirection {
+    pub fn prompt(&self) -> char {
+        match self {
+            SearchDirection::Forward => '/',
+            SearchDirection::Backward => '?',
+        }
+    }
+
+    pub fn reverse(&self) -> Self {
+        match self {
+            SearchDirection::Forward => SearchDirection::Backward,
+            SearchDirection::Backward => SearchDirection::Forward,
+        }
+    }
+}
 
 #[derive(Default)]
 pub struct Search {
     pub query: String,
+    pub direction: SearchDirection,
+    pub highlight: bool,
     pub results: Vec<SearchResult>,
+    regex: Option<Regex>,
+    // Undo revision the results were collected at, they go stale once the buffer changes
+    results_revision: Option<usize>,
 }
 
-#[allow(dead_code)]
+// Columns are grapheme columns, `end` is exclusive
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
 pub struct SearchResult {
-    pub line: String,
     pub row: usize,
     pub start: usize,
     pub end: usize,
 }
 
 impl Search {
-    pub fn search(&mut self, lines: &[String]) {
+    // Queries without uppercase letters match case-insensitively
+    pub fn set_query(&mut self, query: &str) -> Result<()> {
+        let regex = RegexBuilder::new(query)
+            .case_insensitive(!has_uppercase(query))
+            .build()
+            .map_err(|_| anyhow!("E383: Invalid search string: {}", query))?;
+        self.query = query.to_string();
+        self.regex = Some(regex);
         self.results.clear();
-        for (i, line) in lines.iter().enumerate() {
-            if let Some(start) = line.find(&self.query) {
-                self.results.push(SearchResult {
-                    line: line.to_string(),
-                    row: i,
+        self.results_revision = None;
+        Ok(())
+    }
+
+    pub fn line_matches(&self, line: &str, row: usize) -> Vec<SearchResult> {
+        let Some(regex) = &self.regex else {
+            return vec![];
+        };
+
+        regex
+            .find_iter(line)
+            .map(|m| {
+                let start = grapheme_count(&line[..m.start()]);
+                SearchResult {
+                    row,
                     start,
-                    end: start + self.query.len(),
-                });
+                    end: start + grapheme_count(m.as_str()),
+                }
+            })
+            .collect()
+    }
+
+    pub fn search(&mut self, text: &TextBuffer, revision: usize) {
+        if self.results_revision == Some(revision) {
+            return;
+        }
+
+        self.results.clear();
+        for (i, line) in text.lines().enumerate() {
+            let matches = self.line_matches(&line, i);
+            self.results.extend(matches);
+        }
+        self.results_revision = Some(revision);
+    }
+
+    // First match strictly after (or before) `from`, wrapping around the buffer. The flag is
+    // set when the search wrapped.
+    pub fn find_from(
+        &self,
+        text: &TextBuffer,
+        from: Cursor,
+        direction: SearchDirection,
+    ) -> Option<(SearchResult, bool)> {
+        self.regex.as_ref()?;
+        let len = text.len_lines();
+        let from_y = from.y.min(len - 1);
+        for step in 0..=len {
+            let y = match direction {
+                SearchDirection::Forward => (from_y + step) % len,
+                SearchDirection::Backward => (from_y + len - step) % len,
+            };
+            let line = text.line(y).unwrap_or_default();
+            let matches = self.line_matches(&line, y);
+            let found = match (direction, step) {
+                (SearchDirection::Forward, 0) => matches.into_iter().find(|m| m.start > from.x),
+                (SearchDirection::Forward, s) if s == len => {
+                    matches.into_iter().find(|m| m.start <= from.x)
+                }
+                (SearchDirection::Forward, _) => matches.into_iter().next(),
+                (SearchDirection::Backward, 0) => {
+                    matches.into_iter().rev().find(|m| m.start < from.x)
+                }
+                (SearchDirection::Backward, s) if s == len => {
+                    matches.into_iter().rev().find(|m| m.start >= from.x)
+                }
+                (SearchDirection::Backward, _) => matches.into_iter().next_back(),
+            };
+            if let Some(found) = found {
+                let wrapped = match direction {
+                    SearchDirection::Forward => y < from_y || step == len,
+                    SearchDirection::Backward => y > from_y || step == len,
+                };
+                return Some((found, wrapped));
+            }
+        }
+        None
+    }
+}
+
+// Smart case, a pattern with an uppercase letter in it is case sensitive. An escaped character
+// such as \S or \W is a class or a literal, not a letter that was typed.
+fn has_uppercase(query: &str) -> bool {
+    let mut chars = query.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => {
+                chars.next();
             }
+            c if c.is_uppercase() => return true,
+            _ => {}
         }
     }
+    false
 }
@@ -0,0 +1,207 @@
+use unicode_segmentation::UnicodeSegmentation;
+
+use crate::{
+    cursor::Cursor,
+    normal::OperatorRange,
+    text_buffer::TextBuffer,
+    unicode::{col_at_display, display_col, grapheme_count, grapheme_width},
+};
+
+#[derive(Clone, Copy, Default, PartialEq, Eq, Debug)]
+pub enum VisualKind {
+    // v
+    #[default]
+    Char,
+    // V
+    Line,
+    // CTRL-V
+    Block,
+}
+
+// The text selected in Visual mode, from where it was started to the cursor with both ends
+// included
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Selection {
+    pub anchor: Cursor,
+    pub cursor: Cursor,
+    pub kind: VisualKind,
+    // $ was used in Visual Block mode, the block reaches the end of every line
+    pub to_end: bool,
+}
+
+impl Selection {
+    // The two ends in text order
+    pub fn ordered(&self) -> (Cursor, Cursor) {
+        match (self.anchor.y, self.anchor.x) <= (self.cursor.y, self.cursor.x) {
+            true => (self.anchor, self.cursor),
+            false => (self.cursor, self.anchor),
+        }
+    }
+
+    // Left and right display column of a block, both included. A wide character or tab at
+    // either end is in it as a whole.
+    pub fn columns(&self, text: &TextBuffer, tab_width: usize) -> (usize, usize) {
+        let edges = |pos: Cursor| {
+            let line = text.line(pos.y).unwrap_or_default();
+            let col = display_col(&line, pos.x, tab_width);
+            let width = match line.graphemes(true).nth(pos.x) {
+                Some(g) => grapheme_width(g, col, tab_width).max(1),
+                None => 1,
+            };
+            (col, col + width - 1)
+        };
+        let (anchor, cursor) = (edges(self.anchor), edges(self.cursor));
+        (anchor.0.min(cursor.0), anchor.1.max(cursor.1))
+    }
+
+    // Columns of line `y` drawn selected, the end excluded, or None when none of it is
+    pub fn row_span(
+        &self,
+        text: &TextBuffer,
+        y: usize,
+        tab_width: usize,
+    ) -> Option<(usize, usize)> {
+        let (start, end) = self.ordered();
+        if y < start.y || y > end.y {
+            return None;
+        }
+        match self.kind {
+            VisualKind::Char => {
+                let left = if y == start.y { start.x } else { 0 };
+                let right = if y == end.y { end.x + 1 } else { usize::MAX };
+                Some((left, right))
+            }
+            VisualKind::Line => Some((0, usize::MAX)),
+            VisualKind::Block => {
+                let (left, right) = self.columns(text, tab_width);
+                let right = match self.to_end {
+                    true => usize::MAX,
+                    false => right + 1,
+                };
+                let line = text.line(y).unwrap_or_default();
+                Some(block_span(&line, left, right, tab_width))
+            }
+        }
+    }
+
+    // The text an operator typed in Visual mode acts on
+    pub fn range(&self, text: &TextBuffer, tab_width: usize) -> OperatorRange {
+        let (start, end) = self.ordered();
+        match self.kind {
+            // On an empty line the line break is what is selected
+            VisualKind::Char => {
+                let after = (text.pos_to_char(end) + 1).min(text.len_chars());
+                OperatorRange::Chars(start, text.char_to_pos(after))
+            }
+            VisualKind::Line => OperatorRange::Lines(start.y, end.y),
+            VisualKind::Block => {
+                let (left, right) = self.columns(text, tab_width);
+                let right = match self.to_end {
+                    true => usize::MAX,
+                    false => right + 1,
+                };
+                OperatorRange::Block((left, start.y).into(), (right, end.y).into())
+            }
+        }
+    }
+}
+
+// Columns of `line` under the display columns left..right, with the wide characters and tabs
+// only partly under them
+pub fn block_span(line: &str, left: usize, right: usize, tab_width: usize) -> (usize, usize) {
+    let start = col_at_display(line, left, tab_width);
+    let end = match right > left {
+        true => col_at_display(line, right - 1, tab_width).saturating_add(1),
+        false => start,
+    };
+    (start, end.min(grapheme_count(line)).max(start))
+}
+
+// Text typed on the first row of a block by I, A or c, which goes into the other rows too when
+// Insert mode ends
+#[derive(Clone, Copy, Debug)]
+pub struct BlockInsert {
+    pub top: usize,
+    pub bottom: usize,
+    // Display column the text goes in at, None for the end of every line after $A
+    pub col: Option<usize>,
+    // A pads lines shorter than the column with spaces, I and c leave them alone
+    pub pad: bool,
+    // Where typing started on the first row and how long the row was then
+    pub x: usize,
+    pub len: usize,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn text(s: &str) -> TextBuffer {
+        let mut text = TextBuffer::default();
+        text.insert(0, s);
+        text
+    }
+
+    fn block(anchor: (usize, usize), cursor: (usize, usize)) -> Selection {
+        Selection {
+            anchor: anchor.into(),
+            cursor: cursor.into(),
+            kind: VisualKind::Block,
+            to_end: false,
+        }
+    }
+
+    #[test]
+    fn block_columns_are_display_columns() {
+        let text = text("日本語\nabcdef");
+        // From the second wide character down to the f under its right half
+        let selection = block((1, 0), (3, 1));
+        assert_eq!(selection.columns(&text, 8), (2, 3));
+        assert_eq!(selection.row_span(&text, 0, 8), Some((1, 2)));
+        assert_eq!(selection.row_span(&text, 1, 8), Some((2, 4)));
+        assert_eq!(
+            selection.range(&text, 8),
+            OperatorRange::Block((2, 0).into(), (4, 1).into())
+        );
+    }
+
+    #[test]
+    fn block_takes_in_wide_characters_and_tabs_at_its_edges() {
+        let text = text("abcdef\n日本語\n\tx");
+        let selection = block((1, 0), (2, 0));
+        assert_eq!(selection.columns(&text, 4), (1, 2));
+        // Both characters that are partly in columns 1 and 2
+        assert_eq!(block_span("日本語", 1, 3, 4), (0, 2));
+        // The tab covers columns 0 to 3
+        assert_eq!(block_span("\tx", 1, 3, 4), (0, 1));
+        let selection = block((0, 2), (1, 0));
+        assert_eq!(selection.columns(&text, 4), (0, 3));
+    }
+
+    #[test]
+    fn block_on_short_lines() {
+        let text = text("abcdef\nab\n\nabcdef");
+        let mut selection = block((3, 0), (4, 3));
+        assert_eq!(selection.row_span(&text, 1, 8), Some((2, 2)));
+        assert_eq!(selection.row_span(&text, 2, 8), Some((0, 0)));
+        assert_eq!(selection.row_span(&text, 3, 8), Some((3, 5)));
+        selection.to_end = true;
+        assert_eq!(selection.row_span(&text, 3, 8), Some((3, 6)));
+        assert_eq!(block_span("abc", 1, usize::MAX, 8), (1, 3));
+    }
+
+    #[test]
+    fn char_and_line_rows() {
+        let text = text("abc\ndef\nghi");
+        let mut selection = Selection {
+            kind: VisualKind::Char,
+            ..block((1, 2), (1, 0))
+        };
+        assert_eq!(selection.row_span(&text, 0, 8), Some((1, usize::MAX)));
+        assert_eq!(selection.row_span(&text, 1, 8), Some((0, usize::MAX)));
+        assert_eq!(selection.row_span(&text, 2, 8), Some((0, 2)));
+        selection.kind = VisualKind::Line;
+        assert_eq!(selection.row_span(&text, 2, 8), Some((0, usize::MAX)));
+        assert_eq!(selection.row_span(&text, 3, 8), None);
+    }
+}
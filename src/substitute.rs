@@ -0,0 +1,233 @@
+use anyhow::{anyhow, Result};
+use regex::{Regex, RegexBuilder};
+
+use crate::{search::SearchResult, text_buffer::TextBuffer, unicode::grapheme_count};
+
+#[derive(Clone, Debug, Default)]
+pub struct Substitute {
+    pub pattern: String,
+    pub replacement: String,
+    pub global: bool,
+    pub confirm: bool,
+    // `i` and `I` flags, otherwise smart-case like searching
+    pub ignore_case: Option<bool>,
+}
+
+// Splits off the next `delimiter` terminated field, unescaping escaped delimiters
+fn split_field(input: &str, delimiter: char) -> (String, Option<&str>) {
+    let mut field = String::new();
+    let mut chars = input.char_indices();
+    while let Some((i, c)) = chars.next() {
+        if c == delimiter {
+            return (field, Some(&input[i + c.len_utf8()..]));
+        }
+        if c == '\\' {
+            match chars.next() {
+                Some((_, next)) if next == delimiter => field.push(next),
+                Some((_, next)) => {
+                    field.push(c);
+                    field.push(next);
+                }
+                None => field.push(c),
+            }
+            continue;
+        }
+        field.push(c);
+    }
+    (field, None)
+}
+
+// Parses the `/pattern/replacement/flags` argument of :s
+pub fn parse(arg: &str) -> Result<Substitute> {
+    let mut chars = arg.chars();
+    let delimiter = chars
+        .next()
+        .ok_or_else(|| anyhow!("E35: No previous regular expression"))?;
+    if delimiter.is_alphanumeric() || delimiter == '\\' || delimiter == '"' || delimiter == '|' {
+        return Err(anyhow!(
+            "E146: Regular expressions can't be delimited by letters"
+        ));
+    }
+
+    let (pattern, rest) = split_field(chars.as_str(), delimiter);
+    let (replacement, rest) = match rest {
+        Some(rest) => split_field(rest, delimiter),
+        None => (String::new(), None),
+    };
+
+    let mut substitute = Substitute {
+        pattern,
+        replacement,
+        ..Default::default()
+    };
+    for flag in rest.unwrap_or_default().trim().chars() {
+        match flag {
+            'g' => substitute.global = true,
+            'c' => substitute.confirm = true,
+            'i' => substitute.ignore_case = Some(true),
+            'I' => substitute.ignore_case = Some(false),
+            _ => return Err(anyhow!("E488: Trailing characters: {}", flag)),
+        }
+    }
+    Ok(substitute)
+}
+
+// Translates vim replacement syntax (`&`, `\1`, `\r`) into the regex crate's `$` syntax
+pub fn expand_template(replacement: &str) -> String {
+    let mut out = String::new();
+    let mut chars = replacement.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '&' => out.push_str("${0}"),
+            '$' => out.push_str("$$"),
+            '\\' => match chars.next() {
+                Some(d) if d.is_ascii_digit() => out.push_str(&format!("${{{}}}", d)),
+                Some('r') | Some('n') => out.push('\n'),
+                Some('t') => out.push('\t'),
+                Some('$') => out.push_str("$$"),
+                Some(other) => out.push(other),
+                None => out.push('\\'),
+            },
+            _ => out.push(c),
+        }
+    }
+    out
+}
+
+// A substitution in progress. Matches are found one at a time so the same state drives both
+// the plain command and the interactive `c` flag.
+pub struct SubstituteState {
+    regex: Regex,
+    template: String,
+    global: bool,
+    pub confirm: bool,
+    pub row: usize,
+    pub end_row: usize,
+    from: usize,
+    // Byte offset just past the previous match, an empty match there is not a new match
+    last_match_end: Option<usize>,
+    pub current: Option<SubstituteMatch>,
+    pub count: usize,
+    pub lines: usize,
+    pub last_line: Option<usize>,
+}
+
+#[derive(Clone, Debug)]
+pub struct SubstituteMatch {
+    pub result: SearchResult,
+    byte_start: usize,
+    byte_end: usize,
+    pub replacement: String,
+}
+
+impl SubstituteState {
+    pub fn new(substitute: &Substitute, pattern: &str, range: (usize, usize)) -> Result<Self> {
+        let ignore_case = substitute
+            .ignore_case
+            .unwrap_or(!pattern.chars().any(char::is_uppercase));
+        let regex = RegexBuilder::new(pattern)
+            .case_insensitive(ignore_case)
+            .build()
+            .map_err(|_| anyhow!("E383: Invalid search string: {}", pattern))?;
+
+        Ok(Self {
+            regex,
+            template: expand_template(&substitute.replacement),
+            global: substitute.global,
+            confirm: substitute.confirm,
+            row: range.0,
+            end_row: range.1,
+            from: 0,
+            last_match_end: None,
+            current: None,
+            count: 0,
+            lines: 0,
+            last_line: None,
+        })
+    }
+
+    // Finds the next match in range and stores it in `current`
+    pub fn find_next(&mut self, text: &TextBuffer) -> Option<&SubstituteMatch> {
+        self.current = None;
+        while self.row <= self.end_row && self.row < text.len_lines() {
+            let line = text.line(self.row).unwrap_or_default();
+            let mut from = self.from;
+            while from <= line.len() {
+                let Some(caps) = self.regex.captures_at(&line, from) else {
+                    break;
+                };
+                let m = caps.get(0).unwrap();
+                if m.is_empty() && Some(m.start()) == self.last_match_end {
+                    // Step over one character and retry
+                    from = match line[m.start()..].chars().next() {
+                        Some(c) => m.start() + c.len_utf8(),
+                        None => line.len() + 1,
+                    };
+                    continue;
+                }
+
+                let mut replacement = String::new();
+                caps.expand(&self.template, &mut replacement);
+                let start = grapheme_count(&line[..m.start()]);
+                self.current = Some(SubstituteMatch {
+                    result: SearchResult {
+                        row: self.row,
+                        start,
+                        end: start + grapheme_count(m.as_str()),
+                    },
+                    byte_start: m.start(),
+                    byte_end: m.end(),
+                    replacement,
+                });
+                return self.current.as_ref();
+            }
+
+            self.next_row();
+        }
+        None
+    }
+
+    fn next_row(&mut self) {
+        self.row += 1;
+        self.from = 0;
+        self.last_match_end = None;
+    }
+
+    // Moves past the current match once it has been replaced
+    pub fn replaced(&mut self) {
+        let Some(current) = self.current.take() else {
+            return;
+        };
+
+        self.count += 1;
+        if self.last_line != Some(self.row) {
+            self.lines += 1;
+        }
+
+        // Replacements containing line breaks push the rest of the line further down
+        let breaks = current.replacement.matches('\n').count();
+        let end = match current.replacement.rfind('\n') {
+            Some(idx) => current.replacement.len() - idx - 1,
+            None => current.byte_start + current.replacement.len(),
+        };
+        self.row += breaks;
+        self.end_row += breaks;
+        self.last_line = Some(self.row);
+        self.advance(end);
+    }
+
+    pub fn skipped(&mut self) {
+        if let Some(current) = self.current.take() {
+            self.advance(current.byte_end);
+        }
+    }
+
+    fn advance(&mut self, end: usize) {
+        if self.global {
+            self.from = end;
+            self.last_match_end = Some(end);
+        } else {
+            self.next_row();
+        }
+    }
+}
@@ -0,0 +1,201 @@
+use std::sync::Arc;
+
+use crate::text_buffer::TextBuffer;
+
+use super::{Language, Region};
+
+// Highlighting far below the cached lines restarts this many lines above the requested one
+// instead of scanning the whole file, at the cost of missing a region opened further up
+const SYNC_LINES: usize = 200;
+
+// Byte range of a line and the index of its scope in `Language::scopes`
+#[derive(Clone, Copy, Debug)]
+pub struct HighlightSpan {
+    pub start: usize,
+    pub end: usize,
+    pub scope: usize,
+}
+
+struct LineHighlight {
+    spans: Vec<HighlightSpan>,
+    // Region still open at the end of the line
+    end_region: Option<usize>,
+}
+
+// Caches the highlighting of a contiguous run of lines. Edits drop the cache from the edited
+// line down, so only the lines that get drawn are highlighted again.
+pub struct Highlighter {
+    pub language: Arc<Language>,
+    first: usize,
+    lines: Vec<LineHighlight>,
+}
+
+impl Highlighter {
+    pub fn new(language: Arc<Language>) -> Self {
+        Self {
+            language,
+            first: 0,
+            lines: vec![],
+        }
+    }
+
+    pub fn invalidate(&mut self, row: usize) {
+        if row < self.first {
+            self.lines.clear();
+        } else {
+            self.lines.truncate(row - self.first);
+        }
+    }
+
+    pub fn line_spans(&mut self, text: &TextBuffer, row: usize) -> &[HighlightSpan] {
+        if row < self.first || row > self.first + self.lines.len() + SYNC_LINES {
+            self.first = row.saturating_sub(SYNC_LINES);
+            self.lines.clear();
+        }
+
+        while self.first + self.lines.len() <= row {
+            let y = self.first + self.lines.len();
+            let state = self.lines.last().and_then(|l| l.end_region);
+            let line = text.line(y).unwrap_or_default();
+            let highlighted = highlight_line(&self.language, &line, state);
+            self.lines.push(highlighted);
+        }
+        &self.lines[row - self.first].spans
+    }
+}
+
+// End of `region` when it closes on this line
+fn region_end(region: &Region, line: &str, from: usize) -> Option<usize> {
+    let mut pos = from;
+    loop {
+        let end = region.end.find_at(line, pos)?;
+        let skip = region
+            .skip
+            .as_ref()
+            .and_then(|skip| skip.find_at(line, pos))
+            .filter(|skip| !skip.is_empty() && skip.start() < end.start());
+        match skip {
+            Some(skip) => pos = skip.end(),
+            None => return Some(end.end()),
+        }
+    }
+}
+
+// Earliest match of pattern `idx` (regions first, then rules) at or after `pos`, as
+// (match start, highlight start, highlight end)
+fn find_token(
+    language: &Language,
+    idx: usize,
+    line: &str,
+    pos: usize,
+) -> Option<(usize, usize, usize)> {
+    if let Some(region) = language.regions.get(idx) {
+        let m = region.start.find_at(line, pos)?;
+        return Some((m.start(), m.start(), m.end()));
+    }
+
+    let regex = &language.rules[idx - language.regions.len()].regex;
+    if regex.captures_len() > 1 {
+        let caps = regex.captures_at(line, pos)?;
+        let whole = caps.get(0)?;
+        let span = caps.get(1).unwrap_or(whole);
+        return Some((whole.start(), span.start(), span.end()));
+    }
+    let m = regex.find_at(line, pos)?;
+    Some((m.start(), m.start(), m.end()))
+}
+
+fn highlight_line(language: &Language, line: &str, state: Option<usize>) -> LineHighlight {
+    let mut spans = vec![];
+    let mut pos = 0;
+
+    if let Some(idx) = state {
+        let region = &language.regions[idx];
+        match region_end(region, line, 0) {
+            Some(end) => {
+                spans.push(HighlightSpan {
+                    start: 0,
+                    end,
+                    scope: region.scope,
+                });
+                pos = end;
+            }
+            None => {
+                spans.push(HighlightSpan {
+                    start: 0,
+                    end: line.len(),
+                    scope: region.scope,
+                });
+                return LineHighlight {
+                    spans,
+                    end_region: state,
+                };
+            }
+        }
+    }
+
+    // Next match of every pattern, only searched again once `pos` has moved past it
+    let count = language.regions.len() + language.rules.len();
+    let mut next = (0..count)
+        .map(|idx| find_token(language, idx, line, pos))
+        .collect::<Vec<_>>();
+
+    while pos <= line.len() {
+        for (idx, token) in next.iter_mut().enumerate() {
+            if token.is_some_and(|(start, _, _)| start < pos) {
+                *token = find_token(language, idx, line, pos);
+            }
+        }
+
+        // Earliest match wins, ties go to whichever pattern is defined first
+        let Some((idx, (start, span_start, span_end))) = next
+            .iter()
+            .enumerate()
+            .filter_map(|(idx, token)| token.map(|t| (idx, t)))
+            .min_by_key(|(idx, (start, _, _))| (*start, *idx))
+        else {
+            break;
+        };
+
+        if let Some(region) = language.regions.get(idx) {
+            match region_end(region, line, span_end) {
+                Some(end) => {
+                    spans.push(HighlightSpan {
+                        start,
+                        end,
+                        scope: region.scope,
+                    });
+                    pos = end.max(start + 1);
+                }
+                None => {
+                    spans.push(HighlightSpan {
+                        start,
+                        end: line.len(),
+                        scope: region.scope,
+                    });
+                    return LineHighlight {
+                        spans,
+                        end_region: Some(idx),
+                    };
+                }
+            }
+            continue;
+        }
+
+        let rule = &language.rules[idx - language.regions.len()];
+        if span_end > span_start {
+            spans.push(HighlightSpan {
+                start: span_start,
+                end: span_end,
+                scope: rule.scope,
+            });
+        }
+        // Always make progress, even on empty matches
+        pos = span_end.max(start + line[start..].chars().next().map_or(1, char::len_utf8));
+    }
+
+    LineHighlight {
+        spans,
+        end_region: None,
+    }
+}
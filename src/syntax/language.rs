@@ -0,0 +1,179 @@
+use std::{fs, path::Path, sync::Arc};
+
+use anyhow::{anyhow, Context, Result};
+use figment::{
+    providers::{Format, Toml},
+    Figment,
+};
+use regex::Regex;
+use serde::Deserialize;
+
+use crate::word::IsKeyword;
+
+const BUNDLED: &[&str] = &[
+    include_str!("languages/rust.toml"),
+    include_str!("languages/toml.toml"),
+    include_str!("languages/markdown.toml"),
+    include_str!("languages/json.toml"),
+    include_str!("languages/python.toml"),
+    include_str!("languages/shell.toml"),
+];
+
+#[derive(Deserialize)]
+struct RuleDef {
+    scope: String,
+    pattern: String,
+}
+
+#[derive(Deserialize)]
+struct RegionDef {
+    scope: String,
+    start: String,
+    end: String,
+    skip: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct LanguageDef {
+    name: String,
+    #[serde(default)]
+    extensions: Vec<String>,
+    #[serde(default)]
+    filenames: Vec<String>,
+    iskeyword: Option<IsKeyword>,
+    #[serde(default)]
+    regions: Vec<RegionDef>,
+    #[serde(default)]
+    rules: Vec<RuleDef>,
+}
+
+// Single line token. When the pattern has a capture group only the first group is highlighted.
+pub struct Rule {
+    pub scope: usize,
+    pub regex: Regex,
+}
+
+// Span between a start and an end pattern that may cover several lines, like block comments
+pub struct Region {
+    pub scope: usize,
+    pub start: Regex,
+    pub end: Regex,
+    pub skip: Option<Regex>,
+}
+
+pub struct Language {
+    pub name: String,
+    pub extensions: Vec<String>,
+    pub filenames: Vec<String>,
+    // Replaces the iskeyword setting in buffers of this language
+    pub iskeyword: Option<IsKeyword>,
+    pub scopes: Vec<String>,
+    pub regions: Vec<Region>,
+    pub rules: Vec<Rule>,
+}
+
+impl Language {
+    pub fn from_toml(source: &str) -> Result<Self> {
+        let def: LanguageDef = Figment::new().merge(Toml::string(source)).extract()?;
+        let mut scopes: Vec<String> = vec![];
+        let mut scope_idx = |scope: &str| match scopes.iter().position(|s| s == scope) {
+            Some(idx) => idx,
+            None => {
+                scopes.push(scope.to_string());
+                scopes.len() - 1
+            }
+        };
+        let compile = |pattern: &str| {
+            Regex::new(pattern).map_err(|err| anyhow!("Invalid pattern {:?}: {}", pattern, err))
+        };
+
+        let mut regions = vec![];
+        for region in def.regions {
+            regions.push(Region {
+                scope: scope_idx(&region.scope),
+                start: compile(&region.start)?,
+                end: compile(&region.end)?,
+                skip: region.skip.as_deref().map(compile).transpose()?,
+            });
+        }
+
+        let mut rules = vec![];
+        for rule in def.rules {
+            rules.push(Rule {
+                scope: scope_idx(&rule.scope),
+                regex: compile(&rule.pattern)?,
+            });
+        }
+
+        Ok(Self {
+            name: def.name,
+            extensions: def.extensions,
+            filenames: def.filenames,
+            iskeyword: def.iskeyword,
+            scopes,
+            regions,
+            rules,
+        })
+    }
+
+    fn matches(&self, path: &Path) -> bool {
+        let file_name = path.file_name().and_then(|n| n.to_str());
+        let extension = path.extension().and_then(|e| e.to_str());
+        file_name.is_some_and(|name| self.filenames.iter().any(|f| f == name))
+            || extension.is_some_and(|ext| self.extensions.iter().any(|e| e == ext))
+    }
+}
+
+#[derive(Default)]
+pub struct LanguageRegistry {
+    languages: Vec<Arc<Language>>,
+}
+
+impl LanguageRegistry {
+    // Loads the bundled definitions, then every *.toml file in `user_dir`. A user definition
+    // replaces a bundled one with the same name. Files that fail to load are reported back
+    // without stopping the rest.
+    pub fn load(user_dir: &Path) -> (Self, Vec<String>) {
+        let mut registry = Self::default();
+        let mut errors = vec![];
+        for source in BUNDLED {
+            match Language::from_toml(source) {
+                Ok(language) => registry.add(language),
+                Err(err) => errors.push(format!("Bundled syntax failed to load: {}", err)),
+            }
+        }
+
+        let Ok(entries) = fs::read_dir(user_dir) else {
+            return (registry, errors);
+        };
+        let mut paths = entries
+            .filter_map(|entry| entry.ok().map(|e| e.path()))
+            .filter(|path| path.extension().is_some_and(|ext| ext == "toml"))
+            .collect::<Vec<_>>();
+        paths.sort();
+        for path in paths {
+            let language = fs::read_to_string(&path)
+                .map_err(anyhow::Error::from)
+                .and_then(|source| Language::from_toml(&source))
+                .with_context(|| format!("Failed to load syntax {}", path.display()));
+            match language {
+                Ok(language) => registry.add(language),
+                Err(err) => errors.push(format!("{:#}", err)),
+            }
+        }
+        (registry, errors)
+    }
+
+    fn add(&mut self, language: Language) {
+        self.languages.retain(|l| l.name != language.name);
+        self.languages.push(Arc::new(language));
+    }
+
+    pub fn for_path(&self, path: &Path) -> Option<Arc<Language>> {
+        self.languages
+            .iter()
+            .rev()
+            .find(|l| l.matches(path))
+            .cloned()
+    }
+}
@@ -0,0 +1,4 @@
+mod highlighter;
+mod language;
+pub use highlighter::*;
+pub use language::*;
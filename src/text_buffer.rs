@@ -0,0 +1,117 @@
+use std::{
+    borrow::Cow,
+    fs::File,
+    io::{self, BufReader, BufWriter, Write},
+    ops::Range,
+    path::Path,
+};
+
+use ropey::Rope;
+
+use crate::{
+    cursor::Cursor,
+    unicode::{grapheme_count, grapheme_to_byte},
+};
+
+// Rope backed text storage. Positions are (grapheme column, line) pairs, edits address the rope
+// by char index. Lines never include their trailing newline.
+#[derive(Clone, Default)]
+pub struct TextBuffer {
+    rope: Rope,
+}
+
+impl TextBuffer {
+    pub fn from_file(path: &Path) -> io::Result<Self> {
+        let rope = Rope::from_reader(BufReader::new(File::open(path)?))?;
+        Ok(Self { rope })
+    }
+
+    pub fn write_to_file(&self, path: &Path) -> io::Result<()> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        self.rope.write_to(&mut writer)?;
+        writer.flush()
+    }
+
+    pub fn len_lines(&self) -> usize {
+        self.rope.len_lines()
+    }
+
+    pub fn line(&self, y: usize) -> Option<Cow<'_, str>> {
+        if y >= self.len_lines() {
+            return None;
+        }
+
+        let line: Cow<str> = self.rope.line(y).into();
+        Some(match line {
+            Cow::Borrowed(s) => Cow::Borrowed(s.strip_suffix('\n').unwrap_or(s)),
+            Cow::Owned(mut s) => {
+                if s.ends_with('\n') {
+                    s.pop();
+                }
+                Cow::Owned(s)
+            }
+        })
+    }
+
+    pub fn lines(&self) -> impl Iterator<Item = Cow<'_, str>> {
+        (0..self.len_lines()).filter_map(|y| self.line(y))
+    }
+
+    // Length of the line in grapheme clusters
+    pub fn line_len(&self, y: usize) -> usize {
+        self.line(y).map(|line| grapheme_count(&line)).unwrap_or(0)
+    }
+
+    pub fn len_chars(&self) -> usize {
+        self.rope.len_chars()
+    }
+
+    pub fn pos_to_char(&self, pos: Cursor) -> usize {
+        let y = pos.y.min(self.len_lines() - 1);
+        let line = self.line(y).unwrap_or_default();
+        let byte = grapheme_to_byte(&line, pos.x);
+        self.rope.line_to_char(y) + line[..byte].chars().count()
+    }
+
+    pub fn char_to_pos(&self, idx: usize) -> Cursor {
+        let idx = idx.min(self.len_chars());
+        let y = self.rope.char_to_line(idx);
+        let prefix: Cow<str> = self.rope.slice(self.rope.line_to_char(y)..idx).into();
+        (grapheme_count(&prefix), y).into()
+    }
+
+    pub fn char(&self, idx: usize) -> Option<char> {
+        self.rope.get_char(idx)
+    }
+
+    // Chars from `idx` on, and the ones before it going backwards
+    pub fn chars_at(&self, idx: usize) -> impl Iterator<Item = char> + '_ {
+        self.rope.chars_at(idx)
+    }
+
+    pub fn chars_before(&self, idx: usize) -> impl Iterator<Item = char> + '_ {
+        self.rope.chars_at(idx).reversed()
+    }
+
+    pub fn byte_to_char(&self, idx: usize) -> usize {
+        self.rope.byte_to_char(idx)
+    }
+
+    pub fn char_to_byte(&self, idx: usize) -> usize {
+        self.rope.char_to_byte(idx)
+    }
+
+    pub fn insert(&mut self, idx: usize, text: &str) {
+        self.rope.insert(idx, text);
+    }
+
+    pub fn remove(&mut self, range: Range<usize>) {
+        self.rope.remove(range);
+    }
+
+    pub fn slice(&self, start: Cursor, end: Cursor) -> String {
+        let start = self.pos_to_char(start);
+        let end = self.pos_to_char(end).max(start);
+        self.rope.slice(start..end).to_string()
+    }
+}
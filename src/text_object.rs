@@ -0,0 +1,589 @@
+use std::sync::LazyLock;
+
+use regex::Regex;
+use unicode_segmentation::UnicodeSegmentation;
+
+use crate::{
+    cursor::Cursor,
+    normal::OperatorRange,
+    text_buffer::TextBuffer,
+    word::{CharKind, IsKeyword},
+};
+
+// Text objects select the thing around the cursor. The inner variant (`iw`, `i(`) is the
+// thing itself, the around variant (`aw`, `a(`) adds its whitespace or delimiters.
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ObjectKind {
+    Word,
+    // Anything between whitespace, vim's WORD
+    BigWord,
+    Sentence,
+    Paragraph,
+    Quote(char),
+    Bracket(char, char),
+    Tag,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct TextObject {
+    pub kind: ObjectKind,
+    pub around: bool,
+}
+
+static TAG: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"<(/)?([A-Za-z][^\s/>]*)[^>]*?(/)?>").unwrap());
+
+// The range of `object` at `cursor`, None when there is nothing to select. A count selects
+// that many words, sentences or paragraphs, or the count-th enclosing bracket pair or tag.
+pub fn select(
+    text: &TextBuffer,
+    cursor: Cursor,
+    object: TextObject,
+    count: usize,
+    keyword: &IsKeyword,
+) -> Option<OperatorRange> {
+    let count = count.max(1);
+    let around = object.around;
+    match object.kind {
+        ObjectKind::Word => word(text, cursor, around, count, keyword, false),
+        ObjectKind::BigWord => word(text, cursor, around, count, keyword, true),
+        ObjectKind::Sentence => sentence(text, cursor, around, count),
+        ObjectKind::Paragraph => paragraph(text, cursor, around, count),
+        ObjectKind::Quote(quote) => quoted(text, cursor, around, quote),
+        ObjectKind::Bracket(open, close) => bracket(text, cursor, around, count, open, close),
+        ObjectKind::Tag => tag(text, cursor, around, count),
+    }
+}
+
+fn chars(text: &TextBuffer, start: usize, end: usize) -> OperatorRange {
+    OperatorRange::Chars(text.char_to_pos(start), text.char_to_pos(end))
+}
+
+// Words stay within the line
+fn word(
+    text: &TextBuffer,
+    cursor: Cursor,
+    around: bool,
+    count: usize,
+    keyword: &IsKeyword,
+    big: bool,
+) -> Option<OperatorRange> {
+    let line = text.line(cursor.y)?;
+    let kinds = line
+        .graphemes(true)
+        .map(|g| CharKind::of(g, keyword, big))
+        .collect::<Vec<_>>();
+    if kinds.is_empty() {
+        return None;
+    }
+
+    let len = kinds.len();
+    let x = cursor.x.min(len - 1);
+    // Every word counted is at least a grapheme long
+    let count = count.min(len);
+    // End of the run of same kind graphemes starting at `from`
+    let next_run = |from: usize| match from < len {
+        true => (from..len)
+            .find(|&i| kinds[i] != kinds[from])
+            .unwrap_or(len),
+        false => len,
+    };
+    let mut start = (0..=x).rev().take_while(|&i| kinds[i] == kinds[x]).last()?;
+    let mut end = next_run(start);
+
+    let on_space = kinds[x] == CharKind::Space;
+    if !around {
+        for _ in 1..count {
+            end = next_run(end);
+        }
+    } else if on_space {
+        // The whitespace and the word after it
+        end = next_run(end);
+        for _ in 1..count {
+            end = next_run(next_run(end));
+        }
+    } else {
+        // The word and the whitespace after it, or before it when there is none after
+        for i in 0..count {
+            if i > 0 {
+                end = next_run(end);
+            }
+            if end < len && kinds[end] == CharKind::Space {
+                end = next_run(end);
+            }
+        }
+        if kinds[end - 1] != CharKind::Space {
+            while start > 0 && kinds[start - 1] == CharKind::Space {
+                start -= 1;
+            }
+        }
+    }
+    Some(OperatorRange::Chars(
+        (start, cursor.y).into(),
+        (end, cursor.y).into(),
+    ))
+}
+
+// Quotes pair up from the start of the line, a backslash escapes one. Outside of a pair the
+// next pair on the line is used.
+fn quoted(text: &TextBuffer, cursor: Cursor, around: bool, quote: char) -> Option<OperatorRange> {
+    let line = text.line(cursor.y)?;
+    let graphemes = line.graphemes(true).collect::<Vec<_>>();
+    let quotes = graphemes
+        .iter()
+        .enumerate()
+        .filter(|(i, g)| g.starts_with(quote) && (*i == 0 || graphemes[i - 1] != "\\"))
+        .map(|(i, _)| i)
+        .collect::<Vec<_>>();
+
+    let before = quotes.iter().filter(|&&q| q < cursor.x).count();
+    let (open, close) = match quotes.iter().position(|&q| q == cursor.x) {
+        Some(i) if i % 2 == 0 => (quotes[i], *quotes.get(i + 1)?),
+        Some(i) => (quotes[i - 1], quotes[i]),
+        None if before % 2 == 1 => (quotes[before - 1], *quotes.get(before)?),
+        None => (*quotes.get(before)?, *quotes.get(before + 1)?),
+    };
+
+    if !around {
+        return Some(OperatorRange::Chars(
+            (open + 1, cursor.y).into(),
+            (close, cursor.y).into(),
+        ));
+    }
+    let is_space = |i: usize| graphemes.get(i).is_some_and(|g| g.trim().is_empty());
+    let (mut start, mut end) = (open, close + 1);
+    if is_space(end) {
+        while is_space(end) {
+            end += 1;
+        }
+    } else {
+        while start > 0 && is_space(start - 1) {
+            start -= 1;
+        }
+    }
+    Some(OperatorRange::Chars(
+        (start, cursor.y).into(),
+        (end, cursor.y).into(),
+    ))
+}
+
+fn bracket(
+    text: &TextBuffer,
+    cursor: Cursor,
+    around: bool,
+    count: usize,
+    open: char,
+    close: char,
+) -> Option<OperatorRange> {
+    // Unmatched `open` before `from`, walking out from it and stopping there
+    let find_open = |from: usize| {
+        let mut depth = 0;
+        for (i, c) in (0..from).rev().zip(text.chars_before(from)) {
+            match c {
+                c if c == close => depth += 1,
+                c if c == open && depth == 0 => return Some(i),
+                c if c == open => depth -= 1,
+                _ => {}
+            }
+        }
+        None
+    };
+    let find_close = |from: usize| {
+        let mut depth = 0;
+        for (i, c) in (from..).zip(text.chars_at(from)) {
+            match c {
+                c if c == open => depth += 1,
+                c if c == close && depth == 0 => return Some(i),
+                c if c == close => depth -= 1,
+                _ => {}
+            }
+        }
+        None
+    };
+
+    let idx = text.pos_to_char(cursor);
+    let mut start = match text.char(idx) {
+        Some(c) if c == open => idx,
+        _ => find_open(idx)?,
+    };
+    for _ in 1..count {
+        start = find_open(start)?;
+    }
+    let end = find_close(start + 1)?;
+    if around {
+        return Some(chars(text, start, end + 1));
+    }
+
+    // A block with the brackets on lines of their own is inner as whole lines
+    let (open_pos, close_pos) = (text.char_to_pos(start), text.char_to_pos(end));
+    let close_line = text.line(close_pos.y).unwrap_or_default();
+    let close_indented = close_line
+        .graphemes(true)
+        .take(close_pos.x)
+        .all(|g| g.trim().is_empty());
+    if text.char(start + 1) == Some('\n') && close_pos.y > open_pos.y + 1 && close_indented {
+        return Some(OperatorRange::Lines(open_pos.y + 1, close_pos.y - 1));
+    }
+    // An empty pair has no inside to act on, so di( fails on ()
+    if end == start + 1 {
+        return None;
+    }
+    Some(chars(text, start + 1, end))
+}
+
+// Lines on each side of the cursor that tags are first looked for in
+const TAG_LINES: usize = 100;
+
+// Matching open and close tags, tags without a match are skipped. Only the lines near the cursor
+// are searched, twice as many each time the tags aren't found, so a tag object in a big file
+// doesn't go through all of it.
+fn tag(text: &TextBuffer, cursor: Cursor, around: bool, count: usize) -> Option<OperatorRange> {
+    let last_line = text.len_lines() - 1;
+    let mut lines = TAG_LINES;
+    loop {
+        let (first, last) = (
+            cursor.y.saturating_sub(lines),
+            (cursor.y + lines).min(last_line),
+        );
+        match tag_in(text, first, last, cursor, around, count) {
+            None if first > 0 || last < last_line => lines *= 2,
+            range => return range,
+        }
+    }
+}
+
+// tag() within lines `first` to `last`
+fn tag_in(
+    text: &TextBuffer,
+    first: usize,
+    last: usize,
+    cursor: Cursor,
+    around: bool,
+    count: usize,
+) -> Option<OperatorRange> {
+    let contents = text.slice((0, first).into(), (text.line_len(last), last).into());
+    // Byte offset of the searched lines in the text
+    let base = text.char_to_byte(text.pos_to_char((0, first).into()));
+    let mut open = Vec::<(&str, usize, usize)>::new();
+    let mut pairs = Vec::new();
+    for captures in TAG.captures_iter(&contents) {
+        let all = captures.get(0)?;
+        let name = captures.get(2)?.as_str();
+        if captures.get(3).is_some() {
+            continue;
+        }
+        if captures.get(1).is_none() {
+            open.push((name, all.start(), all.end()));
+        } else if let Some(idx) = open.iter().rposition(|(open, ..)| *open == name) {
+            let (_, start, inner_start) = open[idx];
+            open.truncate(idx);
+            pairs.push((start, inner_start, all.start(), all.end()));
+        }
+    }
+
+    let byte = text.char_to_byte(text.pos_to_char(cursor)) - base;
+    let mut enclosing = pairs
+        .into_iter()
+        .filter(|(start, _, _, end)| (*start..*end).contains(&byte))
+        .collect::<Vec<_>>();
+    // Innermost first
+    enclosing.sort_by_key(|(start, ..)| std::cmp::Reverse(*start));
+    let (start, inner_start, inner_end, end) = *enclosing.get(count - 1)?;
+    let (start, end) = match around {
+        true => (start, end),
+        false => (inner_start, inner_end),
+    };
+    Some(chars(
+        text,
+        text.byte_to_char(base + start),
+        text.byte_to_char(base + end),
+    ))
+}
+
+fn is_blank(text: &TextBuffer, y: usize) -> bool {
+    text.line(y).is_none_or(|line| line.trim().is_empty())
+}
+
+// Lines around `y` that are all blank or all not blank
+fn line_run(text: &TextBuffer, y: usize) -> (usize, usize) {
+    let blank = is_blank(text, y);
+    let first = (0..y)
+        .rev()
+        .take_while(|&y| is_blank(text, y) == blank)
+        .last()
+        .unwrap_or(y);
+    let last = (y + 1..text.len_lines())
+        .take_while(|&y| is_blank(text, y) == blank)
+        .last()
+        .unwrap_or(y);
+    (first, last)
+}
+
+// A paragraph and the blank lines after it, or before it at the end of the file. On a blank
+// line the around variant takes the blank lines and the paragraph after them.
+fn paragraph(
+    text: &TextBuffer,
+    cursor: Cursor,
+    around: bool,
+    count: usize,
+) -> Option<OperatorRange> {
+    let (mut first, mut last) = line_run(text, cursor.y);
+    let runs = if around {
+        count.saturating_mul(2)
+    } else {
+        count
+    };
+    let mut taken = 1;
+    while taken < runs && last + 1 < text.len_lines() {
+        last = line_run(text, last + 1).1;
+        taken += 1;
+    }
+    if around && taken < runs && !is_blank(text, cursor.y) && first > 0 {
+        first = line_run(text, first - 1).0;
+    }
+    Some(OperatorRange::Lines(first, last))
+}
+
+// Sentences end at `.`, `!` or `?`, optionally followed by closing brackets or quotes, and then
+// whitespace. They never cross a paragraph boundary.
+fn sentence(
+    text: &TextBuffer,
+    cursor: Cursor,
+    around: bool,
+    count: usize,
+) -> Option<OperatorRange> {
+    if is_blank(text, cursor.y) {
+        return None;
+    }
+    let (first, last) = line_run(text, cursor.y);
+    let para_start = text.pos_to_char((0, first).into());
+    let para_end = text.pos_to_char((text.line_len(last), last).into());
+    let is_space = |i: usize| text.char(i).is_some_and(char::is_whitespace);
+
+    // Start and end of the text of each sentence, whitespace between them is left out
+    let mut sentences = Vec::new();
+    let mut start = para_start;
+    let mut i = para_start;
+    while i < para_end {
+        if matches!(text.char(i), Some('.' | '!' | '?')) {
+            let mut end = i + 1;
+            while end < para_end && matches!(text.char(end), Some(')' | ']' | '"' | '\'')) {
+                end += 1;
+            }
+            if end == para_end || is_space(end) {
+                sentences.push((start, end));
+                let mut next = end;
+                while next < para_end && is_space(next) {
+                    next += 1;
+                }
+                start = next;
+                i = next;
+                continue;
+            }
+        }
+        i += 1;
+    }
+    if start < para_end {
+        let mut end = para_end;
+        while end > start && is_space(end - 1) {
+            end -= 1;
+        }
+        sentences.push((start, end));
+    }
+
+    let idx = text.pos_to_char(cursor);
+    let current = sentences
+        .iter()
+        .rposition(|(start, _)| *start <= idx)
+        .unwrap_or(0);
+    let (start, text_end) = sentences[current];
+    let next_start = |n: usize| sentences.get(n).map_or(para_end, |(start, _)| *start);
+
+    // On the whitespace between two sentences
+    if idx >= text_end {
+        let end = match around {
+            true => sentences.get(current + 1).map_or(para_end, |(_, end)| *end),
+            false => next_start(current + 1),
+        };
+        return Some(chars(text, text_end, end));
+    }
+
+    let last = current.saturating_add(count - 1).min(sentences.len() - 1);
+    if !around {
+        return Some(chars(text, start, sentences[last].1));
+    }
+    let end = next_start(last + 1);
+    // Without whitespace after it, the sentence takes the whitespace before it
+    if end == sentences[last].1 && current > 0 {
+        return Some(chars(text, sentences[current - 1].1, end));
+    }
+    Some(chars(text, start, end))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn text(s: &str) -> TextBuffer {
+        let mut text = TextBuffer::default();
+        text.insert(0, s);
+        text
+    }
+
+    // The selected text, with the cursor at the first `at` in `s`
+    fn selected(s: &str, at: &str, kind: ObjectKind, around: bool, count: usize) -> Option<String> {
+        let text = text(s);
+        let idx = s.find(at).expect("the cursor text");
+        let cursor = text.char_to_pos(s[..idx].chars().count());
+        let object = TextObject { kind, around };
+        match select(&text, cursor, object, count, &IsKeyword::default())? {
+            OperatorRange::Chars(start, end) => Some(text.slice(start, end)),
+            range => Some(format!("{range:?}")),
+        }
+    }
+
+    const PARENS: ObjectKind = ObjectKind::Bracket('(', ')');
+
+    #[test]
+    fn empty_pairs() {
+        assert_eq!(selected("f()", ")", PARENS, false, 1), None);
+        assert_eq!(selected("f()", "(", PARENS, true, 1).as_deref(), Some("()"));
+        assert_eq!(
+            selected("a[]", "]", ObjectKind::Bracket('[', ']'), false, 1),
+            None
+        );
+        assert_eq!(
+            selected("<a></a>", "></", ObjectKind::Tag, false, 1).as_deref(),
+            Some("")
+        );
+    }
+
+    #[test]
+    fn nested_brackets() {
+        let s = "f(a, g(b, c), d)";
+        assert_eq!(selected(s, "b", PARENS, false, 1).as_deref(), Some("b, c"));
+        assert_eq!(
+            selected(s, "b", PARENS, false, 2).as_deref(),
+            Some("a, g(b, c), d")
+        );
+        assert_eq!(
+            selected(s, "b", PARENS, true, 2).as_deref(),
+            Some("(a, g(b, c), d)")
+        );
+        assert_eq!(selected(s, "b", PARENS, false, 3), None);
+        // On an opening bracket its own pair is selected, after a closed pair the outer one
+        assert_eq!(selected(s, "(b", PARENS, false, 1).as_deref(), Some("b, c"));
+        assert_eq!(
+            selected(s, ", d", PARENS, false, 1).as_deref(),
+            Some("a, g(b, c), d")
+        );
+    }
+
+    #[test]
+    fn unbalanced_brackets() {
+        assert_eq!(selected("(a", "a", PARENS, false, 1), None);
+        assert_eq!(selected("a)", "a", PARENS, false, 1), None);
+        assert_eq!(
+            selected("x) (a)", "a", PARENS, false, 1).as_deref(),
+            Some("a")
+        );
+        assert_eq!(selected("(a (b)", "a", PARENS, false, 1), None);
+        assert_eq!(
+            selected("(a (b)", "b", PARENS, false, 1).as_deref(),
+            Some("b")
+        );
+    }
+
+    #[test]
+    fn block_on_lines_of_its_own() {
+        let s = "if x {\n    a;\n    b;\n}";
+        let brace = ObjectKind::Bracket('{', '}');
+        assert_eq!(
+            selected(s, "a", brace, false, 1).as_deref(),
+            Some("Lines(1, 2)")
+        );
+        assert_eq!(
+            selected(s, "a", brace, true, 1).as_deref(),
+            Some("{\n    a;\n    b;\n}")
+        );
+    }
+
+    #[test]
+    fn quotes() {
+        let quote = ObjectKind::Quote('"');
+        let s = r#"say "hi there" now"#;
+        assert_eq!(
+            selected(s, "hi", quote, false, 1).as_deref(),
+            Some("hi there")
+        );
+        assert_eq!(
+            selected(s, "hi", quote, true, 1).as_deref(),
+            Some("\"hi there\" ")
+        );
+        // Before the quotes on the line the first pair is taken
+        assert_eq!(
+            selected(s, "say", quote, false, 1).as_deref(),
+            Some("hi there")
+        );
+        assert_eq!(selected("a \"b", "b", quote, false, 1), None);
+    }
+
+    #[test]
+    fn tags() {
+        let s = "<div><b>x</b><br/></div>";
+        assert_eq!(
+            selected(s, "x", ObjectKind::Tag, false, 1).as_deref(),
+            Some("x")
+        );
+        assert_eq!(
+            selected(s, "x", ObjectKind::Tag, true, 1).as_deref(),
+            Some("<b>x</b>")
+        );
+        assert_eq!(
+            selected(s, "x", ObjectKind::Tag, false, 2).as_deref(),
+            Some("<b>x</b><br/>")
+        );
+        assert_eq!(selected(s, "x", ObjectKind::Tag, false, 3), None);
+        assert_eq!(selected("<a>x</b>", "x", ObjectKind::Tag, false, 1), None);
+    }
+
+    #[test]
+    fn huge_counts() {
+        let max = usize::MAX;
+        let s = "foo bar\n\nbaz. qux.\n\nend";
+        assert_eq!(
+            selected(s, "bar", ObjectKind::Word, true, max).as_deref(),
+            Some(" bar")
+        );
+        assert_eq!(
+            selected(s, "baz", ObjectKind::Sentence, false, max).as_deref(),
+            Some("baz. qux.")
+        );
+        assert_eq!(
+            selected(s, "foo", ObjectKind::Paragraph, true, max),
+            Some("Lines(0, 4)".to_string())
+        );
+    }
+
+    #[test]
+    fn words() {
+        let s = "foo  bar.baz";
+        assert_eq!(
+            selected(s, "foo", ObjectKind::Word, false, 1).as_deref(),
+            Some("foo")
+        );
+        assert_eq!(
+            selected(s, "foo", ObjectKind::Word, true, 1).as_deref(),
+            Some("foo  ")
+        );
+        assert_eq!(
+            selected(s, "bar", ObjectKind::Word, false, 3).as_deref(),
+            Some("bar.baz")
+        );
+        assert_eq!(
+            selected(s, "bar", ObjectKind::BigWord, false, 1).as_deref(),
+            Some("bar.baz")
+        );
+    }
+}
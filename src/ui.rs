@@ -1,44 +1,50 @@
 use itertools::Itertools;
 use ratatui::{prelude::*, widgets::*};
 
-use crate::editor::{CurrentScreen, Editor};
+use crate::{
+    buffer::Buffer,
+    editor::{CurrentScreen, Editor, Mode},
+    unicode::display_col,
+};
 
 pub fn ui(f: &mut Frame, editor: &mut Editor) {
+    // The tabline is only shown when there are several tab pages
+    let tabline_height = if editor.tabs.len() > 1 { 1 } else { 0 };
     let full_layout = Layout::default()
         .direction(Direction::Vertical)
         .constraints(vec![
-            Constraint::Max(f.size().height - 2),
-            Constraint::Max(1), // status line
-            Constraint::Max(1), // notif area
+            Constraint::Length(tabline_height), // tabline
+            Constraint::Min(0),                 // windows
+            Constraint::Length(1),              // notif area
         ])
         .split(f.size());
 
-    let buffer_layout = Layout::default()
-        .direction(Direction::Horizontal)
-        .constraints(vec![
-            Constraint::Min(line_number_width(editor) as u16), // line numbers
-            Constraint::Max(1),                                // spacer
-            Constraint::Min(f.size().width - (line_number_width(editor) as u16)), // editor
-                                                               // content
-        ])
-        .split(full_layout[0]);
+    // Background for the areas not drawn below, e.g. the message line
+    f.render_widget(Block::default().style(editor.theme.text), f.size());
 
-    // Main text
-    f.render_widget(editor.widget(), buffer_layout[2]);
+    if tabline_height > 0 {
+        f.render_widget(tabline(editor), full_layout[0]);
+    }
 
-    // Line numbers
-    if editor.settings.line_numbers {
-        f.render_widget(line_numbers(editor), buffer_layout[0]);
+    // Windows, each with its own line numbers and status line
+    let areas = editor.layout.layout(full_layout[1]);
+    editor.window_areas = areas.clone();
+    let mut cursor = None;
+    for (id, area) in areas {
+        if let Some(position) = window(f, editor, id, area, full_layout[1]) {
+            cursor = Some(position);
+        }
     }
 
     // Cursor
-    let cursor_x = editor.cursor.x + buffer_layout[2].x as usize;
-    let cursor_y = (editor.cursor.y + buffer_layout[2].y as usize - editor.scroll.0 as usize)
-        .clamp(0, buffer_layout[2].height as usize - 1);
-    f.set_cursor(cursor_x as u16, cursor_y as u16);
+    if let Mode::Command | Mode::Search = editor.mode {
+        let command_col = display_col(&editor.command, editor.command_x, editor.settings.tab_width);
+        let cursor_x = command_col + 1 + full_layout[2].x as usize;
+        f.set_cursor(cursor_x as u16, full_layout[2].y);
+    } else if let Some((x, y)) = cursor {
+        f.set_cursor(x, y);
+    }
 
-    // Status stuff
-    f.render_widget(statusline(editor), full_layout[1]);
     f.render_widget(statusmessage(editor), full_layout[2]);
 
     // Exit popup
@@ -47,44 +53,245 @@ pub fn ui(f: &mut Frame, editor: &mut Editor) {
         f.render_widget(Clear, area);
         f.render_widget(exit_popup(editor), area);
     }
+
+    // Register contents shown by :registers
+    if let CurrentScreen::Registers(names) = &editor.current_screen {
+        let area = centered_rect(80, 60, f.size());
+        f.render_widget(Clear, area);
+        f.render_widget(register_list(editor, names.as_deref()), area);
+    }
+
+    // Marks shown by :marks
+    if let CurrentScreen::Marks(names) = &editor.current_screen {
+        let area = centered_rect(80, 60, f.size());
+        f.render_widget(Clear, area);
+        f.render_widget(mark_list(editor, names.as_deref()), area);
+    }
+
+    // Buffer switcher opened by :ls
+    if let CurrentScreen::BufferSwitcher = editor.current_screen {
+        let area = centered_rect(60, 50, f.size());
+        f.render_widget(Clear, area);
+        let mut state = ListState::default().with_selected(Some(editor.buffer_switcher_idx));
+        f.render_stateful_widget(buffer_switcher(editor), area, &mut state);
+    }
+}
+
+// Draws window `id` into `area` and returns the screen position of the cursor when it is the
+// current window
+fn window(
+    f: &mut Frame,
+    editor: &mut Editor,
+    id: usize,
+    area: Rect,
+    screen: Rect,
+) -> Option<(u16, u16)> {
+    // Windows with another one to their right get a separator column
+    let mut area = area;
+    if area.right() < screen.right() && area.width > 1 {
+        let separator = Rect::new(area.right().saturating_sub(1), area.y, 1, area.height);
+        let bar = vec!["│"; area.height as usize].join("\n");
+        f.render_widget(
+            Paragraph::new(bar).style(editor.theme.statusline_inactive),
+            separator,
+        );
+        area.width -= 1;
+    }
+
+    let window_layout = Layout::default()
+        .direction(Direction::Vertical)
+        .constraints(vec![
+            Constraint::Min(0),    // text
+            Constraint::Length(1), // status line
+        ])
+        .split(area);
+
+    let gutter_width = line_number_width(editor, editor.window_view(id)?.0) as u16;
+    let buffer_layout = Layout::default()
+        .direction(Direction::Horizontal)
+        .constraints(vec![
+            Constraint::Length(gutter_width), // line numbers, with a space after them
+            Constraint::Min(0),               // editor content
+        ])
+        .split(window_layout[0]);
+
+    // Main text, drawn first as it scrolls the window to its cursor
+    let text = editor.window_widget(id, buffer_layout[1])?;
+    f.render_widget(text, buffer_layout[1]);
+
+    // Line numbers
+    let (buffer, cursor, scroll) = editor.window_view(id)?;
+    if editor.settings.line_numbers {
+        f.render_widget(
+            line_numbers(editor, buffer, scroll, buffer_layout[0].height),
+            buffer_layout[0],
+        );
+    }
+
+    f.render_widget(statusline(editor, id), window_layout[1]);
+
+    if id != editor.window_id || buffer_layout[1].height == 0 {
+        return None;
+    }
+    let line = buffer.text.line(cursor.y).unwrap_or_default();
+    let col = display_col(&line, cursor.x, editor.settings.tab_width);
+    let cursor_x = (col - scroll.1 + buffer_layout[1].x as usize)
+        .min((buffer_layout[1].right() as usize).saturating_sub(1));
+    let cursor_y = (cursor.y + buffer_layout[1].y as usize - scroll.0)
+        .clamp(0, buffer_layout[1].bottom() as usize - 1);
+    Some((cursor_x as u16, cursor_y as u16))
 }
 
-fn line_numbers(editor: &Editor) -> Paragraph {
-    let nums = (1..editor.lines.len() + 1).collect_vec();
-    Paragraph::new(nums.into_iter().join("\n"))
-        .dark_gray()
-        .scroll(editor.scroll)
+fn line_numbers<'a>(
+    editor: &'a Editor,
+    buffer: &Buffer,
+    scroll: (usize, usize),
+    height: u16,
+) -> Paragraph<'a> {
+    let first = scroll.0 + 1;
+    let last = (scroll.0 + height as usize).min(buffer.text.len_lines());
+    Paragraph::new((first..=last).join("\n")).style(editor.theme.gutter)
 }
 
-fn line_number_width(editor: &Editor) -> usize {
+fn line_number_width(editor: &Editor, buffer: &Buffer) -> usize {
     if !editor.settings.line_numbers {
         return 0;
     }
 
-    editor.lines.len().to_string().len() + 1
+    buffer.text.len_lines().to_string().len() + 1
 }
 
-fn statusline(editor: &Editor) -> Paragraph {
+// The mode is only shown in the current window's status line
+fn statusline(editor: &Editor, id: usize) -> Paragraph<'_> {
+    let Some((buffer, cursor, _)) = editor.window_view(id) else {
+        return Paragraph::default();
+    };
+    let active = id == editor.window_id;
+    let mode = match (active, &editor.macros.recording) {
+        (true, Some((name, _))) => format!("{} recording @{} ", editor.mode.name(), name),
+        (true, None) => format!("{} ", editor.mode.name()),
+        (false, _) => String::new(),
+    };
+    let style = if active {
+        editor.theme.statusline
+    } else {
+        editor.theme.statusline_inactive
+    };
     Paragraph::new(format!(
-        "{}:{}",
-        editor.cursor.x + 1,
-        editor.cursor.y + 1
+        "{}{}{} {}:{}",
+        mode,
+        buffer.name(),
+        if buffer.is_dirty() { " [+]" } else { "" },
+        cursor.x + 1,
+        cursor.y + 1
     ))
-    .style(
-        Style::default()
-    )
+    .style(style)
+}
+
+fn tabline(editor: &Editor) -> Paragraph<'_> {
+    let labels = editor
+        .tab_labels()
+        .into_iter()
+        .enumerate()
+        .map(|(idx, label)| {
+            let style = if idx == editor.tab_idx {
+                editor.theme.tabline_selected
+            } else {
+                editor.theme.tabline
+            };
+            Span::styled(label, style)
+        })
+        .collect::<Vec<_>>();
+    Paragraph::new(Line::from(labels)).style(editor.theme.tabline)
 }
 
-fn statusmessage(editor: &Editor) -> Paragraph {
+fn statusmessage(editor: &Editor) -> Paragraph<'_> {
+    match editor.mode {
+        Mode::Command => return Paragraph::new(format!(":{}", editor.command)),
+        Mode::Search => {
+            let prompt = editor.buffer.search.direction.prompt();
+            return Paragraph::new(format!("{}{}", prompt, editor.command));
+        }
+        _ => {}
+    }
+
     Paragraph::new(editor.status_message.clone())
 }
 
-fn exit_popup(_editor: &Editor) -> Paragraph {
+fn exit_popup(editor: &Editor) -> Paragraph<'_> {
     let popup_block = Block::default()
+        .borders(Borders::ALL)
+        .style(editor.theme.popup);
+
+    Paragraph::new("Your changes are unsaved. Are you sure you would like to exit? (y/n)")
+        .block(popup_block)
+}
+
+// One line per buffer in the style of vim's :ls, `%` marks the current buffer and `+` one with
+// unsaved changes
+fn buffer_switcher(editor: &Editor) -> List<'_> {
+    let items = editor.buffer_list().into_iter().map(|buffer| {
+        format!(
+            "{:>3} {} {} \"{}\" line {}",
+            buffer.id,
+            if buffer.id == editor.buffer.id { "%" } else { " " },
+            if buffer.is_dirty() { "+" } else { " " },
+            buffer.name(),
+            buffer.cursor.y + 1
+        )
+    });
+    let block = Block::default()
+        .title("Buffers (Enter: open, d: delete, q: close)")
+        .borders(Borders::ALL);
+
+    List::new(items)
+        .block(block)
+        .style(editor.theme.popup)
+        .highlight_style(editor.theme.selection)
+}
+
+// Vim's :registers layout, control characters shown as ^J, ^? for DEL and <80> for the C1 ones
+fn register_list<'a>(editor: &'a Editor, names: Option<&str>) -> List<'a> {
+    let items = editor
+        .register_list(names)
+        .into_iter()
+        .map(|(name, register)| {
+            let text = register
+                .text
+                .chars()
+                .map(|c| match c {
+                    c if c < ' ' || c == '\x7f' => format!("^{}", ((c as u8) ^ 0x40) as char),
+                    c if c.is_control() => format!("<{:02x}>", c as u32),
+                    c => c.to_string(),
+                })
+                .collect::<String>();
+            format!("  {}  \"{}   {}", register.kind.letter(), name, text)
+        });
+    let block = Block::default()
+        .title("Type Name Content (any key: close)")
+        .borders(Borders::ALL);
+
+    List::new(items).block(block).style(editor.theme.popup)
+}
+
+// Like vim, the text of the line is shown for marks in the current buffer and the file name
+// for the others
+fn mark_list<'a>(editor: &'a Editor, names: Option<&str>) -> List<'a> {
+    let items = editor
+        .mark_list(names)
+        .into_iter()
+        .map(|(name, cursor, file)| {
+            let text = match file {
+                Some(file) => file,
+                None => editor.buffer.text.line(cursor.y).unwrap_or_default().trim().to_string(),
+            };
+            format!(" {} {:>6} {:>4} {}", name, cursor.y + 1, cursor.x, text)
+        });
+    let block = Block::default()
+        .title("mark line  col file/text (any key: close)")
         .borders(Borders::ALL);
 
-    return Paragraph::new("Your changes are unsaved. Are you sure you would like to exit? (y/n)")
-        .block(popup_block);
+    List::new(items).block(block).style(editor.theme.popup)
 }
 
 fn centered_rect(percent_x: u16, percent_y: u16, r: Rect) -> Rect {
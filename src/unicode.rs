@@ -0,0 +1,45 @@
+use unicode_segmentation::UnicodeSegmentation;
+use unicode_width::UnicodeWidthStr;
+
+// Columns throughout the editor count grapheme clusters. These helpers map them to byte
+// offsets and to the display columns a terminal draws them in.
+
+pub fn grapheme_count(s: &str) -> usize {
+    s.graphemes(true).count()
+}
+
+pub fn grapheme_to_byte(s: &str, col: usize) -> usize {
+    s.grapheme_indices(true)
+        .nth(col)
+        .map(|(idx, _)| idx)
+        .unwrap_or(s.len())
+}
+
+pub fn grapheme_width(g: &str, display_col: usize, tab_width: usize) -> usize {
+    if g == "\t" {
+        let tab_width = tab_width.max(1);
+        return tab_width - display_col % tab_width;
+    }
+
+    g.width()
+}
+
+// Display column at which the grapheme at `col` starts
+pub fn display_col(s: &str, col: usize, tab_width: usize) -> usize {
+    s.graphemes(true)
+        .take(col)
+        .fold(0, |width, g| width + grapheme_width(g, width, tab_width))
+}
+
+// Column of the grapheme drawn over display column `display`, or the end of the line when it is
+// narrower than that
+pub fn col_at_display(s: &str, display: usize, tab_width: usize) -> usize {
+    let mut width = 0;
+    for (col, g) in s.graphemes(true).enumerate() {
+        width += grapheme_width(g, width, tab_width);
+        if width > display {
+            return col;
+        }
+    }
+    grapheme_count(s)
+}
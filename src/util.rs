@@ -16,3 +16,14 @@ pub fn pe_config_file_path() -> Result<PathBuf> {
 
     Ok(path)
 }
+
+// User syntax definitions live in a `syntax` directory next to the config file
+pub fn pe_syntax_dir() -> Result<PathBuf> {
+    let config = pe_config_file_path()?;
+    let dir = config
+        .parent()
+        .ok_or_else(|| anyhow!("Failed to get config directory"))?
+        .join("syntax");
+
+    Ok(dir)
+}
@@ -0,0 +1,303 @@
+use ratatui::layout::{Constraint, Direction, Layout, Rect};
+
+use crate::cursor::Cursor;
+
+// View of a buffer. The current window's cursor and scroll live in the current buffer while
+// it is focused and are copied back here when focus moves elsewhere.
+#[derive(Clone, Debug)]
+pub struct Window {
+    pub id: usize,
+    pub buffer_id: usize,
+    pub cursor: Cursor,
+    pub scroll: (usize, usize),
+    // Display column j and k aim for, None for the cursor's own. Unlike the cursor it stays
+    // here while the window is focused.
+    pub preferred_col: Option<usize>,
+}
+
+// Window layout of a tab page along with the window that had the focus in it. The current
+// tab's copy is only brought up to date when switching away from it.
+#[derive(Clone, Debug, Default)]
+pub struct TabPage {
+    pub layout: WindowNode,
+    pub window_id: usize,
+}
+
+// In vim terms: a horizontal split stacks windows (:split), a vertical one puts them side by
+// side (:vsplit)
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum SplitDirection {
+    Horizontal,
+    Vertical,
+}
+
+impl SplitDirection {
+    fn layout_direction(&self) -> Direction {
+        match self {
+            SplitDirection::Horizontal => Direction::Vertical,
+            SplitDirection::Vertical => Direction::Horizontal,
+        }
+    }
+
+    fn size(&self, area: Rect) -> u16 {
+        match self {
+            SplitDirection::Horizontal => area.height,
+            SplitDirection::Vertical => area.width,
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum WindowDirection {
+    Left,
+    Down,
+    Up,
+    Right,
+}
+
+// Smallest window: one line of text plus the status line, or a couple of columns
+const MIN_WINDOW_SIZE: u16 = 2;
+
+#[derive(Clone, Debug)]
+pub enum WindowNode {
+    Leaf(usize),
+    Split {
+        direction: SplitDirection,
+        children: Vec<WindowNode>,
+        // Relative sizes of the children
+        weights: Vec<u16>,
+    },
+}
+
+impl Default for WindowNode {
+    fn default() -> Self {
+        WindowNode::Leaf(1)
+    }
+}
+
+impl WindowNode {
+    pub fn is_leaf(&self, id: usize) -> bool {
+        matches!(self, WindowNode::Leaf(leaf) if *leaf == id)
+    }
+
+    pub fn contains(&self, id: usize) -> bool {
+        match self {
+            WindowNode::Leaf(leaf) => *leaf == id,
+            WindowNode::Split { children, .. } => children.iter().any(|c| c.contains(id)),
+        }
+    }
+
+    // Window ids from top left to bottom right
+    pub fn leaves(&self) -> Vec<usize> {
+        match self {
+            WindowNode::Leaf(id) => vec![*id],
+            WindowNode::Split { children, .. } => {
+                children.iter().flat_map(|c| c.leaves()).collect()
+            }
+        }
+    }
+
+    pub fn layout(&self, area: Rect) -> Vec<(usize, Rect)> {
+        match self {
+            WindowNode::Leaf(id) => vec![(*id, area)],
+            WindowNode::Split {
+                direction,
+                children,
+                weights,
+            } => {
+                let total = weights.iter().map(|w| *w as u32).sum::<u32>().max(1);
+                let constraints = weights
+                    .iter()
+                    .map(|w| Constraint::Ratio(*w as u32, total))
+                    .collect::<Vec<_>>();
+                let areas = Layout::default()
+                    .direction(direction.layout_direction())
+                    .constraints(constraints)
+                    .split(area);
+                children
+                    .iter()
+                    .zip(areas.iter())
+                    .flat_map(|(child, area)| child.layout(*area))
+                    .collect()
+            }
+        }
+    }
+
+    // Puts `new` above or left of `target`, the two sharing the space `target` had
+    pub fn split(&mut self, target: usize, new: usize, direction: SplitDirection) -> bool {
+        match self {
+            WindowNode::Leaf(id) if *id == target => {
+                *self = WindowNode::Split {
+                    direction,
+                    children: vec![WindowNode::Leaf(new), WindowNode::Leaf(target)],
+                    weights: vec![1, 1],
+                };
+                true
+            }
+            WindowNode::Leaf(_) => false,
+            WindowNode::Split {
+                direction: split_direction,
+                children,
+                weights,
+            } => {
+                let idx = children.iter().position(|c| c.is_leaf(target));
+                if let (Some(idx), true) = (idx, *split_direction == direction) {
+                    // Doubling keeps the other children's sizes while halving the target
+                    weights.iter_mut().for_each(|w| *w = w.saturating_mul(2));
+                    weights[idx] /= 2;
+                    weights.insert(idx, weights[idx]);
+                    children.insert(idx, WindowNode::Leaf(new));
+                    return true;
+                }
+                children
+                    .iter_mut()
+                    .any(|child| child.split(target, new, direction))
+            }
+        }
+    }
+
+    // Removes a window, splits left with a single child collapse into it
+    pub fn remove(&mut self, target: usize) -> bool {
+        let WindowNode::Split {
+            children, weights, ..
+        } = self
+        else {
+            return false;
+        };
+
+        let removed = match children.iter().position(|c| c.is_leaf(target)) {
+            Some(idx) => {
+                children.remove(idx);
+                weights.remove(idx);
+                true
+            }
+            None => children.iter_mut().any(|child| child.remove(target)),
+        };
+        if children.len() == 1 {
+            *self = children.remove(0);
+        }
+        removed
+    }
+
+    pub fn equalize(&mut self) {
+        if let WindowNode::Split {
+            children, weights, ..
+        } = self
+        {
+            weights.iter_mut().for_each(|w| *w = 1);
+            children.iter_mut().for_each(WindowNode::equalize);
+        }
+    }
+
+    // Grows (or shrinks) `target` by `delta` rows or columns, taking the space from the next
+    // window in the closest split going that way. `areas` is the last drawn layout, the
+    // weights are reset to the actual sizes so the change is exact. Returns None when
+    // `target` isn't in this subtree and Some(false) when it is but nothing was resized yet.
+    pub fn resize(
+        &mut self,
+        target: usize,
+        direction: SplitDirection,
+        delta: isize,
+        areas: &[(usize, Rect)],
+    ) -> Option<bool> {
+        let WindowNode::Split {
+            direction: split_direction,
+            children,
+            weights,
+        } = self
+        else {
+            return self.contains(target).then_some(false);
+        };
+
+        let (idx, done) = children
+            .iter_mut()
+            .enumerate()
+            .find_map(|(idx, child)| Some((idx, child.resize(target, direction, delta, areas)?)))?;
+        if done || *split_direction != direction {
+            return Some(done);
+        }
+
+        let sizes = children
+            .iter()
+            .map(|child| {
+                child
+                    .leaves()
+                    .iter()
+                    .filter_map(|id| areas.iter().find(|(area_id, _)| area_id == id))
+                    .map(|(_, area)| *area)
+                    .reduce(|a, b| a.union(b))
+                    .map_or(MIN_WINDOW_SIZE, |area| direction.size(area))
+            })
+            .collect::<Vec<_>>();
+        let other = if idx + 1 < children.len() {
+            idx + 1
+        } else {
+            idx - 1
+        };
+        let pair = sizes[idx] + sizes[other];
+        if pair < MIN_WINDOW_SIZE * 2 {
+            return Some(true);
+        }
+        let size = (sizes[idx] as isize + delta)
+            .clamp(MIN_WINDOW_SIZE as isize, (pair - MIN_WINDOW_SIZE) as isize)
+            as u16;
+        *weights = sizes;
+        weights[idx] = size;
+        weights[other] = pair - size;
+        Some(true)
+    }
+}
+
+// Closest window on the given side of `current` in the last drawn layout, preferring the one
+// lined up with the cursor row or column
+pub fn neighbour(
+    areas: &[(usize, Rect)],
+    current: usize,
+    direction: WindowDirection,
+    cursor: (u16, u16),
+) -> Option<usize> {
+    let (_, from) = areas.iter().find(|(id, _)| *id == current)?;
+    let overlaps = |a: (u16, u16), b: (u16, u16)| a.0 < b.1 && b.0 < a.1;
+    areas
+        .iter()
+        .filter(|(id, _)| *id != current)
+        .filter_map(|(id, area)| {
+            let (distance, along) =
+                match direction {
+                    WindowDirection::Left if area.right() <= from.left() => {
+                        overlaps((area.top(), area.bottom()), (from.top(), from.bottom()))
+                            .then_some((
+                                from.left() - area.right(),
+                                (area.top(), area.bottom(), cursor.1),
+                            ))?
+                    }
+                    WindowDirection::Right if area.left() >= from.right() => {
+                        overlaps((area.top(), area.bottom()), (from.top(), from.bottom()))
+                            .then_some((
+                                area.left() - from.right(),
+                                (area.top(), area.bottom(), cursor.1),
+                            ))?
+                    }
+                    WindowDirection::Up if area.bottom() <= from.top() => {
+                        overlaps((area.left(), area.right()), (from.left(), from.right()))
+                            .then_some((
+                                from.top() - area.bottom(),
+                                (area.left(), area.right(), cursor.0),
+                            ))?
+                    }
+                    WindowDirection::Down if area.top() >= from.bottom() => {
+                        overlaps((area.left(), area.right()), (from.left(), from.right()))
+                            .then_some((
+                                area.top() - from.bottom(),
+                                (area.left(), area.right(), cursor.0),
+                            ))?
+                    }
+                    _ => return None,
+                };
+            let (start, end, at) = along;
+            let lined_up = (start..end).contains(&at);
+            Some((distance, !lined_up, start, *id))
+        })
+        .min()
+        .map(|(_, _, _, id)| id)
+}
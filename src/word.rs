@@ -1,64 +1,352 @@
 // SOURCE: https://github.com/rhysd/tui-textarea/blob/main/src/word.rs
 
-#[derive(PartialEq, Eq, Clone, Copy)]
-enum CharKind {
+use anyhow::{anyhow, Error, Result};
+use serde::{Deserialize, Serialize};
+use unicode_segmentation::UnicodeSegmentation;
+
+use crate::{cursor::Cursor, text_buffer::TextBuffer};
+
+// Words are runs of keyword characters or runs of other non-blank characters, WORDs are runs of
+// anything but blanks. The motions go through line breaks as if they were blanks, and stop on
+// empty lines, except for e and E.
+
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub enum CharKind {
     Space,
     Punct,
+    // Keyword characters
     Other,
 }
 
 impl CharKind {
-    fn new(c: char) -> Self {
+    fn new(c: char, keyword: &IsKeyword) -> Self {
         if c.is_whitespace() {
             Self::Space
-        } else if c.is_ascii_punctuation() {
+        } else if keyword.contains(c) || (c as u32 > 255 && c.is_alphanumeric()) {
+            Self::Other
+        } else {
             Self::Punct
+        }
+    }
+
+    // Grapheme clusters are classified by their base character. For WORDs anything that isn't
+    // blank is the same kind.
+    pub fn of(g: &str, keyword: &IsKeyword, big: bool) -> Self {
+        match Self::new(g.chars().next().unwrap_or(' '), keyword) {
+            Self::Punct if big => Self::Other,
+            kind => kind,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+enum KeywordPart {
+    // @, the letters
+    Letters,
+    Range(char, char),
+}
+
+// The characters words are made of, set like vim's 'iskeyword' with comma separated parts: a
+// character, a range such as a-z or 48-57 given as characters or their codes, or @ for the
+// letters. A part starting with ^ takes the characters back out. Only characters below 256 are
+// looked up, letters and digits above that are always keyword characters.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct IsKeyword {
+    spec: String,
+    // Later parts win, false for the excluded ones
+    parts: Vec<(bool, KeywordPart)>,
+}
+
+impl IsKeyword {
+    pub fn parse(spec: &str) -> Result<Self> {
+        let parts = spec
+            .split(',')
+            .filter(|part| !part.is_empty())
+            .map(|part| {
+                parse_keyword_part(part)
+                    .ok_or_else(|| anyhow!("E474: Invalid argument: iskeyword={}", spec))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        Ok(Self {
+            spec: spec.to_string(),
+            parts,
+        })
+    }
+
+    pub fn contains(&self, c: char) -> bool {
+        self.parts
+            .iter()
+            .rev()
+            .find(|(_, part)| match part {
+                KeywordPart::Letters => c.is_alphabetic() && (c as u32) < 256,
+                KeywordPart::Range(first, last) => (*first..=*last).contains(&c),
+            })
+            .is_some_and(|(include, _)| *include)
+    }
+}
+
+// vim's default, letters, digits, _ and the accented Latin-1 letters
+impl Default for IsKeyword {
+    fn default() -> Self {
+        Self::parse("@,48-57,_,192-255").unwrap()
+    }
+}
+
+impl TryFrom<String> for IsKeyword {
+    type Error = Error;
+
+    fn try_from(spec: String) -> Result<Self> {
+        Self::parse(&spec)
+    }
+}
+
+impl From<IsKeyword> for String {
+    fn from(keyword: IsKeyword) -> Self {
+        keyword.spec
+    }
+}
+
+fn parse_keyword_part(part: &str) -> Option<(bool, KeywordPart)> {
+    let (include, part) = match part.strip_prefix('^') {
+        Some(rest) if !rest.is_empty() => (false, rest),
+        _ => (true, part),
+    };
+    let part = match part.split_once('-') {
+        _ if part == "@" => KeywordPart::Letters,
+        Some((first, last)) if !first.is_empty() && !last.is_empty() => {
+            KeywordPart::Range(keyword_char(first)?, keyword_char(last)?)
+        }
+        _ => {
+            let c = keyword_char(part)?;
+            KeywordPart::Range(c, c)
+        }
+    };
+    Some((include, part))
+}
+
+// A character written as itself or as its code
+fn keyword_char(s: &str) -> Option<char> {
+    if s.chars().all(|c| c.is_ascii_digit()) {
+        return char::from_u32(s.parse().ok()?);
+    }
+    let mut chars = s.chars();
+    let c = chars.next()?;
+    chars.next().is_none().then_some(c)
+}
+
+// Whether `col` is the last grapheme of a word, or of a WORD
+pub fn is_word_end(line: &str, col: usize, keyword: &IsKeyword, big: bool) -> bool {
+    let mut kinds = line
+        .graphemes(true)
+        .skip(col)
+        .map(|g| CharKind::of(g, keyword, big));
+    match kinds.next() {
+        Some(kind) => kind != CharKind::Space && kinds.next() != Some(kind),
+        None => false,
+    }
+}
+
+// Steps through the graphemes of the text one at a time. The end of each line, where x is the
+// length of the line, is a position of its own and counts as a blank.
+struct Walk<'a> {
+    text: &'a TextBuffer,
+    keyword: &'a IsKeyword,
+    big: bool,
+    pos: Cursor,
+    // Kinds of the graphemes of line pos.y
+    kinds: Vec<CharKind>,
+}
+
+impl<'a> Walk<'a> {
+    fn new(text: &'a TextBuffer, pos: Cursor, keyword: &'a IsKeyword, big: bool) -> Self {
+        let mut walk = Self {
+            text,
+            keyword,
+            big,
+            pos,
+            kinds: vec![],
+        };
+        walk.load_line();
+        walk.pos.x = walk.pos.x.min(walk.kinds.len());
+        walk
+    }
+
+    fn load_line(&mut self) {
+        let line = self.text.line(self.pos.y).unwrap_or_default();
+        self.kinds = line
+            .graphemes(true)
+            .map(|g| CharKind::of(g, self.keyword, self.big))
+            .collect();
+    }
+
+    fn kind(&self) -> CharKind {
+        self.kinds
+            .get(self.pos.x)
+            .copied()
+            .unwrap_or(CharKind::Space)
+    }
+
+    fn on_empty_line(&self) -> bool {
+        self.kinds.is_empty()
+    }
+
+    fn on_last_line(&self) -> bool {
+        self.pos.y + 1 >= self.text.len_lines()
+    }
+
+    // False at the end of the text
+    fn next(&mut self) -> bool {
+        if self.pos.x < self.kinds.len() {
+            self.pos.x += 1;
+        } else if !self.on_last_line() {
+            self.pos = (0, self.pos.y + 1).into();
+            self.load_line();
         } else {
-            Self::Other
+            return false;
+        }
+        true
+    }
+
+    // False at the start of the text
+    fn prev(&mut self) -> bool {
+        if self.pos.x > 0 {
+            self.pos.x -= 1;
+        } else if self.pos.y > 0 {
+            self.pos.y -= 1;
+            self.load_line();
+            self.pos.x = self.kinds.len();
+        } else {
+            return false;
+        }
+        true
+    }
+
+    // Moves past the graphemes of `kind`, false when the text ends first
+    fn skip(&mut self, kind: CharKind, forward: bool) -> bool {
+        while self.kind() == kind {
+            let moved = match forward {
+                true => self.next(),
+                false => self.prev(),
+            };
+            if !moved {
+                return false;
+            }
+        }
+        true
+    }
+}
+
+// w and W. From the last character of the text there is nowhere to go and `pos` comes back, from
+// the last word the end of the text does.
+pub fn next_word_start(text: &TextBuffer, pos: Cursor, keyword: &IsKeyword, big: bool) -> Cursor {
+    let mut walk = Walk::new(text, pos, keyword, big);
+    let start = walk.kind();
+    let last_line = walk.on_last_line();
+    if !walk.next() || (last_line && walk.pos.x == walk.kinds.len()) {
+        return pos;
+    }
+    if start != CharKind::Space && !walk.skip(start, true) {
+        return walk.pos;
+    }
+    while walk.kind() == CharKind::Space && !(walk.pos.x == 0 && walk.on_empty_line()) {
+        if !walk.next() {
+            break;
         }
     }
+    walk.pos
 }
 
-pub fn find_word_start_forward(line: &str, start_col: usize) -> Option<usize> {
-    let mut it = line.chars().enumerate().skip(start_col);
-    let mut prev = CharKind::new(it.next()?.1);
-    for (col, c) in it {
-        let cur = CharKind::new(c);
-        if cur != CharKind::Space && prev != cur {
-            return Some(col);
+// e and E
+pub fn next_word_end(text: &TextBuffer, pos: Cursor, keyword: &IsKeyword, big: bool) -> Cursor {
+    let mut walk = Walk::new(text, pos, keyword, big);
+    let start = walk.kind();
+    if !walk.next() {
+        return pos;
+    }
+    // In the middle of a word its own end is next
+    if start == CharKind::Space || walk.kind() != start {
+        while walk.kind() == CharKind::Space {
+            if !walk.next() {
+                return pos;
+            }
         }
-        prev = cur;
     }
-    None
+    if !walk.skip(walk.kind(), true) {
+        return pos;
+    }
+    walk.prev();
+    walk.pos
 }
 
-pub fn find_word_end_forward(line: &str, start_col: usize) -> Option<usize> {
-    let mut it = line.chars().enumerate().skip(start_col);
-    let mut prev = CharKind::new(it.next()?.1);
-    for (col, c) in it {
-        let cur = CharKind::new(c);
-        if prev != CharKind::Space && prev != cur {
-            return Some(col);
+// b and B
+pub fn prev_word_start(text: &TextBuffer, pos: Cursor, keyword: &IsKeyword, big: bool) -> Cursor {
+    let mut walk = Walk::new(text, pos, keyword, big);
+    if !walk.prev() {
+        return pos;
+    }
+    while walk.kind() == CharKind::Space {
+        if walk.pos.x == 0 && walk.on_empty_line() {
+            return walk.pos;
         }
-        prev = cur;
-    }
-    None
-}
-
-pub fn find_word_start_backward(line: &str, start_col: usize) -> Option<usize> {
-    let idx = line
-        .char_indices()
-        .nth(start_col)
-        .map(|(i, _)| i)
-        .unwrap_or(line.len());
-    let mut it = line[..idx].chars().rev().enumerate();
-    let mut cur = CharKind::new(it.next()?.1);
-    for (i, c) in it {
-        let next = CharKind::new(c);
-        if cur != CharKind::Space && next != cur {
-            return Some(start_col - i);
+        if !walk.prev() {
+            return walk.pos;
         }
-        cur = next;
     }
-    (cur != CharKind::Space).then_some(0)
+    if walk.skip(walk.kind(), false) {
+        walk.next();
+    }
+    walk.pos
+}
+
+// ge and gE
+pub fn prev_word_end(text: &TextBuffer, pos: Cursor, keyword: &IsKeyword, big: bool) -> Cursor {
+    let mut walk = Walk::new(text, pos, keyword, big);
+    let start = walk.kind();
+    if !walk.prev() {
+        return pos;
+    }
+    if start != CharKind::Space && !walk.skip(start, false) {
+        return walk.pos;
+    }
+    while walk.kind() == CharKind::Space && !(walk.pos.x == 0 && walk.on_empty_line()) {
+        if !walk.prev() {
+            break;
+        }
+    }
+    walk.pos
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn kind(c: &str, spec: &str) -> CharKind {
+        CharKind::of(c, &IsKeyword::parse(spec).unwrap(), false)
+    }
+
+    #[test]
+    fn latin1_follows_iskeyword() {
+        assert_eq!(kind("é", "@,48-57,_,192-255"), CharKind::Other);
+        // The last part to name a character decides, even over @
+        assert_eq!(kind("é", "@,48-57,_,192-255,^192-255"), CharKind::Punct);
+        assert_eq!(kind("é", "^192-255,@"), CharKind::Other);
+        assert_eq!(kind("é", "_"), CharKind::Punct);
+        assert_eq!(kind("×", "@"), CharKind::Punct);
+        // Past Latin-1 letters and digits are always keyword characters
+        assert_eq!(kind("α", "_"), CharKind::Other);
+        assert_eq!(kind("日", "^1-65535"), CharKind::Other);
+        assert_eq!(kind("—", "@"), CharKind::Punct);
+    }
+
+    #[test]
+    fn iskeyword_parts() {
+        assert_eq!(kind("-", "@,-"), CharKind::Other);
+        assert_eq!(kind("a", "@,^a-c"), CharKind::Punct);
+        assert_eq!(kind("d", "@,^a-c"), CharKind::Other);
+        assert_eq!(kind("5", "@"), CharKind::Punct);
+        assert_eq!(kind(" ", "1-255"), CharKind::Space);
+        assert!(IsKeyword::parse("a-").is_err());
+        assert!(IsKeyword::parse("ab").is_err());
+    }
 }